@@ -2,7 +2,10 @@ use std::str::FromStr;
 
 use anyhow::Result;
 use solana_sdk::pubkey::Pubkey;
-use solana_trading_balance::subscribe::{subscribe_balance_udpates_by_owner, GrpcConfig};
+use solana_trading_balance::subscribe::{
+    subscribe_balance_udpates_by_owner, GrpcConfig, SubscribeConfig,
+};
+use solana_trading_util::parse::parse_pubkey;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,16 +18,17 @@ async fn main() -> Result<()> {
     let x_token = std::env::var("GRPC_TOKEN").unwrap();
     let wallet = std::env::var("WALLET").unwrap();
 
-    let mut rx = subscribe_balance_udpates_by_owner(
+    let (mut rx, _handle) = subscribe_balance_udpates_by_owner(
         GrpcConfig {
             endpoint,
             x_token: Some(x_token),
         },
-        &Pubkey::from_str(wallet.as_str()).unwrap(),
+        &parse_pubkey(&wallet)?,
         &vec![(
             Pubkey::from_str("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap(),
             false,
         )],
+        SubscribeConfig::default(),
     )
     .await
     .unwrap();
@@ -1 +1,2 @@
 pub mod subscribe;
+pub mod tracker;
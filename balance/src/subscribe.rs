@@ -1,29 +1,95 @@
+use std::collections::HashMap;
+
 use anyhow::{anyhow, Result};
-use solana_sdk::{program_pack::Pack, pubkey::Pubkey, system_program};
-use solana_trading_util::token::mints_to_associated_token_accounts;
-use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, program_pack::Pack, pubkey::Pubkey, system_program,
+};
+use solana_trading_util::token::{mints_to_associated_token_accounts, MAX_MULTIPLE_ACCOUNTS};
+use spl_token_2022::extension::StateWithExtensions;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 use yellowstone_grpc_proto::geyser::{
-    SubscribeRequestFilterAccounts, SubscribeUpdateAccount, SubscribeUpdateAccountInfo,
+    subscribe_request_filter_accounts_filter::Filter as AccountsFilterOneof,
+    subscribe_request_filter_accounts_filter_memcmp::Data as AccountsFilterMemcmpData,
+    SubscribeRequestFilterAccounts, SubscribeRequestFilterAccountsFilter,
+    SubscribeRequestFilterAccountsFilterMemcmp, SubscribeUpdateAccount, SubscribeUpdateAccountInfo,
 };
 use {
-    futures::{sink::SinkExt, stream::StreamExt},
+    futures::{sink::Sink, sink::SinkExt, stream::Stream, stream::StreamExt},
     tokio::time::{interval, Duration},
-    tonic::transport::channel::ClientTlsConfig,
+    tonic::{transport::channel::ClientTlsConfig, Status},
     yellowstone_grpc_client::GeyserGrpcClient,
     yellowstone_grpc_proto::prelude::{
         subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest, SubscribeRequestPing,
-        SubscribeUpdatePong,
+        SubscribeUpdate, SubscribeUpdatePong,
     },
 };
 
+/// Hosts of geyser providers rejecting connections without an x-token.
+const X_TOKEN_REQUIRED_HOSTS: [&str; 5] = [
+    "helius-rpc.com",
+    "rpcpool.com",
+    "quiknode.pro",
+    "shyft.to",
+    "triton.one",
+];
+
 #[derive(Debug)]
 pub struct BalanceUpdate {
     pub is_native: bool,
     pub pubkey: Pubkey,
     pub mint: Option<Pubkey>,
+    /// Wallet holding the balance: the account itself for native balances, the token account
+    /// owner for SPL balances.
+    pub owner: Pubkey,
     pub amount: u64,
+    /// Amount of the previous update received for the account, `None` for the first one.
+    pub previous_amount: Option<u64>,
     pub slot: u64,
+    /// Global counter of account writes from geyser, increasing with every write across all
+    /// slots, so `(slot, write_version)` orders the updates. Always 0 for snapshots, which RPC
+    /// does not version.
+    pub write_version: u64,
+    pub source: UpdateSource,
+}
+
+/// Origin of a `BalanceUpdate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateSource {
+    /// Received from the geyser stream.
+    Stream,
+    /// Synthesized from an RPC snapshot of the account.
+    Snapshot,
+}
+
+/// Handle of a running balance subscription.
+/// Dropping it leaves the subscription running until its receiver is dropped.
+pub struct SubscriptionHandle {
+    cancellation_token: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl SubscriptionHandle {
+    /// Stops the subscription, closing its receiver.
+    pub fn cancel(&self) {
+        self.cancellation_token.cancel();
+    }
+
+    /// Stops the subscription and waits for its connection to be closed.
+    pub async fn shutdown(self) {
+        self.cancellation_token.cancel();
+        if let Err(err) = self.task.await {
+            error!("balance subscription task: {}", err);
+        }
+    }
+
+    /// Whether the subscription has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
 }
 
 pub struct GrpcConfig {
@@ -31,97 +97,441 @@ pub struct GrpcConfig {
     pub x_token: Option<String>,
 }
 
+/// Account data filter applied by the geyser node.
+#[derive(Debug, Clone)]
+pub enum AccountFilter {
+    /// Matches accounts whose data is exactly this many bytes.
+    Datasize(u64),
+    /// Matches accounts whose data contains `bytes` at `offset`.
+    Memcmp { offset: u64, bytes: Vec<u8> },
+}
+
+impl From<AccountFilter> for SubscribeRequestFilterAccountsFilter {
+    fn from(filter: AccountFilter) -> Self {
+        let filter = match filter {
+            AccountFilter::Datasize(size) => AccountsFilterOneof::Datasize(size),
+            AccountFilter::Memcmp { offset, bytes } => {
+                AccountsFilterOneof::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                    offset,
+                    data: Some(AccountsFilterMemcmpData::Bytes(bytes)),
+                })
+            }
+        };
+        SubscribeRequestFilterAccountsFilter {
+            filter: Some(filter),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SubscribeConfig {
+    /// Filters the accounts of `owner_programs` must all match to be delivered. The explicit
+    /// accounts are not filtered, and the filters are ignored without `owner_programs`.
+    pub filters: Vec<AccountFilter>,
+    /// Programs whose accounts are subscribed to, e.g. the token program combined with
+    /// `AccountFilter::Datasize(165)` and an `AccountFilter::Memcmp` on the token account owner
+    /// at offset 32. Sent as a separate geyser filter, so that their accounts are delivered in
+    /// addition to the explicit accounts.
+    pub owner_programs: Vec<Pubkey>,
+    /// Commitment of the streamed updates and of the initial snapshot. Defaults to processed,
+    /// which is the fastest but can deliver balances that are later rolled back by a fork.
+    pub commitment: Option<CommitmentConfig>,
+    /// Backoff used to re-establish the subscription when the stream ends or fails.
+    pub reconnect: ReconnectConfig,
+    /// When set, the current state of the accounts is fetched from this RPC endpoint and sent
+    /// as `UpdateSource::Snapshot` updates before the streamed ones, at startup and after each
+    /// reconnect so that the changes missed during the gap are not lost.
+    pub snapshot_rpc_url: Option<String>,
+}
+
+/// Exponential backoff between reconnect attempts.
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Delay before the first reconnect attempt, doubled after each failed attempt.
+    pub initial_delay: Duration,
+    /// Maximum delay between reconnect attempts.
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
 /// Subscribe to the native balance and SPL balances belonging to an owner
 pub async fn subscribe_balance_udpates_by_owner(
     grpc_config: GrpcConfig,
     owner: &Pubkey,
     mints: &Vec<(Pubkey, bool)>,
-) -> Result<mpsc::Receiver<BalanceUpdate>> {
-    let mut accounts: Vec<Pubkey> = vec![owner.clone()];
-    let ata_accounts = mints_to_associated_token_accounts(owner, mints);
-    accounts.extend(ata_accounts);
+    subscribe_config: SubscribeConfig,
+) -> Result<(mpsc::Receiver<BalanceUpdate>, SubscriptionHandle)> {
+    subscribe_balance_updates_by_owners(
+        grpc_config,
+        &[(owner.clone(), mints.clone())],
+        subscribe_config,
+    )
+    .await
+}
+
+/// Subscribe to the native balances and SPL balances of several owners over a single
+/// connection. Updates are attributed through `BalanceUpdate::owner`.
+pub async fn subscribe_balance_updates_by_owners(
+    grpc_config: GrpcConfig,
+    owners: &[(Pubkey, Vec<(Pubkey, bool)>)],
+    subscribe_config: SubscribeConfig,
+) -> Result<(mpsc::Receiver<BalanceUpdate>, SubscriptionHandle)> {
+    let mut accounts: Vec<Pubkey> = vec![];
+    for (owner, mints) in owners {
+        accounts.push(owner.clone());
+        accounts.extend(mints_to_associated_token_accounts(owner, mints));
+    }
 
-    subscribe_balance_udpates(grpc_config, &accounts).await
+    subscribe_balance_udpates(grpc_config, &accounts, subscribe_config).await
 }
 
+/// Same as `subscribe_balance_udpates`, returning the updates as a `Stream`.
+pub async fn subscribe_balance_stream(
+    grpc_config: GrpcConfig,
+    accounts: &Vec<Pubkey>,
+    subscribe_config: SubscribeConfig,
+) -> Result<(impl Stream<Item = BalanceUpdate>, SubscriptionHandle)> {
+    let (rx, handle) = subscribe_balance_udpates(grpc_config, accounts, subscribe_config).await?;
+    Ok((ReceiverStream::new(rx), handle))
+}
+
+/// Subscribes to the updates of `accounts`. When the stream ends or fails, the subscription
+/// is re-established with the same accounts, backing off as set in `SubscribeConfig::reconnect`,
+/// until the receiver is dropped or the subscription is shut down through the returned handle.
 pub async fn subscribe_balance_udpates(
     grpc_config: GrpcConfig,
     accounts: &Vec<Pubkey>,
-) -> Result<mpsc::Receiver<BalanceUpdate>> {
-    let mut client = GeyserGrpcClient::build_from_shared(grpc_config.endpoint)?
-        .x_token(grpc_config.x_token)?
-        .tls_config(ClientTlsConfig::new().with_native_roots())?
-        .connect()
-        .await?;
-    let (mut subscribe_tx, mut stream) = client.subscribe().await?;
-
-    subscribe_tx
-        .send(SubscribeRequest {
-            accounts: maplit::hashmap! {
-                "client".to_owned() => SubscribeRequestFilterAccounts {
-                    nonempty_txn_signature: None,
-                    account: accounts
-                        .into_iter()
-                        .map(|account| account.to_string())
-                        .collect(),
-                    owner: vec![],
-                    filters: vec![],
-                }
-            },
-            commitment: Some(CommitmentLevel::Processed as i32),
-            ..Default::default()
-        })
-        .await?;
+    subscribe_config: SubscribeConfig,
+) -> Result<(mpsc::Receiver<BalanceUpdate>, SubscriptionHandle)> {
+    let endpoint = grpc_config.endpoint;
+    let x_token = grpc_config.x_token;
+    let auth_hint = if x_token.is_none() {
+        if requires_x_token(&endpoint) {
+            warn!(
+                endpoint = %endpoint,
+                "no x_token configured for a geyser provider that requires authentication"
+            );
+        }
+        " (no x_token configured, the endpoint may require authentication)"
+    } else {
+        ""
+    };
+
+    let commitment = subscribe_config
+        .commitment
+        .unwrap_or(CommitmentConfig::processed());
+    let request = subscribe_request(
+        accounts,
+        &subscribe_config.owner_programs,
+        subscribe_config.filters,
+        commitment,
+    );
+    let reconnect = subscribe_config.reconnect;
+
+    let (mut subscribe_tx, mut stream) =
+        connect_and_subscribe(&endpoint, x_token.clone(), request.clone(), auth_hint).await?;
 
     let (tx, rx) = mpsc::channel::<BalanceUpdate>(1024 * 1024);
 
-    tokio::spawn(async move {
-        let mut timer = interval(Duration::from_secs(3));
-        let mut id = 0;
-        loop {
-            timer.tick().await;
-            id += 1;
-            if let Err(err) = subscribe_tx
-                .send(SubscribeRequest {
-                    ping: Some(SubscribeRequestPing { id }),
-                    ..Default::default()
-                })
+    let mut last_amounts: HashMap<Pubkey, u64> = HashMap::new();
+
+    // The snapshot is taken once subscribed, so that no change is missed in between.
+    let snapshot_rpc = subscribe_config.snapshot_rpc_url.map(RpcClient::new);
+    if let Some(rpc) = &snapshot_rpc {
+        let snapshot = snapshot_balance_updates(rpc, accounts, commitment).await?;
+        for balance_update in snapshot {
+            last_amounts.insert(balance_update.pubkey, balance_update.amount);
+            tx.send(balance_update)
                 .await
-            {
-                error!(error = %err, "could not send ping");
-            }
+                .map_err(|err| anyhow!("send snapshot: {}", err))?;
         }
-    });
+    }
+    let snapshot_accounts = accounts.clone();
 
-    tokio::spawn(async move {
-        info!("start listening");
-        while let Some(message) = stream.next().await {
-            match message?.update_oneof.expect("valid message") {
-                UpdateOneof::Ping(_msg) => {
-                    debug!("ping received");
+    let cancellation_token = CancellationToken::new();
+    let task_token = cancellation_token.clone();
+    let task = tokio::spawn(async move {
+        let mut ping_timer = interval(Duration::from_secs(3));
+        let mut ping_id = 0;
+        loop {
+            info!("start listening");
+            let disconnect_reason = loop {
+                tokio::select! {
+                    _ = task_token.cancelled() => {
+                        info!("balance subscription cancelled, stop listening");
+                        return;
+                    }
+                    _ = tx.closed() => {
+                        info!("balance receiver dropped, stop listening");
+                        return;
+                    }
+                    _ = ping_timer.tick() => {
+                        ping_id += 1;
+                        if let Err(err) = subscribe_tx
+                            .send(SubscribeRequest {
+                                ping: Some(SubscribeRequestPing { id: ping_id }),
+                                ..Default::default()
+                            })
+                            .await
+                        {
+                            error!(error = %err, "could not send ping");
+                        }
+                    }
+                    message = stream.next() => {
+                        let message = match message {
+                            Some(Ok(message)) => message,
+                            Some(Err(err)) => break format!("stream error: {}", err),
+                            None => break "stream ended".to_owned(),
+                        };
+                        let Some(update) = message.update_oneof else {
+                            warn!("received a message without update, skipping");
+                            continue;
+                        };
+                        match update {
+                            UpdateOneof::Ping(_msg) => {
+                                debug!("ping received");
+                            }
+                            UpdateOneof::Pong(SubscribeUpdatePong { id }) => {
+                                debug!("pong received: id#{id}");
+                            }
+                            UpdateOneof::Account(SubscribeUpdateAccount { account, slot, .. }) => {
+                                if let Some(account) = account {
+                                    match get_balance_update(account, slot) {
+                                        Ok(mut balance_update) => {
+                                            balance_update.previous_amount = last_amounts
+                                                .insert(balance_update.pubkey, balance_update.amount);
+                                            if tx.send(balance_update).await.is_err() {
+                                                info!("balance receiver dropped, stop listening");
+                                                return;
+                                            }
+                                        }
+                                        Err(err) => error!("get_balance_update: {}", err),
+                                    }
+                                };
+                            }
+                            msg => break format!("received unexpected message: {msg:?}"),
+                        }
+                    }
                 }
-                UpdateOneof::Pong(SubscribeUpdatePong { id }) => {
-                    debug!("pong received: id#{id}");
+            };
+
+            let mut delay = reconnect.initial_delay;
+            loop {
+                warn!(
+                    endpoint = %endpoint,
+                    reason = %disconnect_reason,
+                    delay = ?delay,
+                    "balance subscription disconnected, reconnecting"
+                );
+                let reconnect_result = tokio::select! {
+                    _ = task_token.cancelled() => {
+                        info!("balance subscription cancelled, stop reconnecting");
+                        return;
+                    }
+                    _ = tx.closed() => {
+                        info!("balance receiver dropped, stop reconnecting");
+                        return;
+                    }
+                    result = async {
+                        tokio::time::sleep(delay).await;
+                        connect_and_subscribe(&endpoint, x_token.clone(), request.clone(), auth_hint)
+                            .await
+                    } => result,
+                };
+                match reconnect_result {
+                    Ok((new_subscribe_tx, new_stream)) => {
+                        subscribe_tx = new_subscribe_tx;
+                        stream = new_stream;
+                        break;
+                    }
+                    Err(err) => {
+                        error!("reconnect failed: {}", err);
+                        delay = (delay * 2).min(reconnect.max_delay);
+                    }
                 }
-                UpdateOneof::Account(SubscribeUpdateAccount { account, slot, .. }) => {
-                    if let Some(account) = account {
-                        match get_balance_update(account, slot) {
-                            Ok(balance_update) => {
-                                if let Err(err) = tx.send(balance_update).await {
-                                    error!("send error: {}", err);
-                                }
+            }
+
+            // Catch up on the changes missed while disconnected.
+            if let Some(rpc) = &snapshot_rpc {
+                match snapshot_balance_updates(rpc, &snapshot_accounts, commitment).await {
+                    Ok(snapshot) => {
+                        for mut balance_update in snapshot {
+                            balance_update.previous_amount =
+                                last_amounts.insert(balance_update.pubkey, balance_update.amount);
+                            if tx.send(balance_update).await.is_err() {
+                                info!("balance receiver dropped, stop listening");
+                                return;
                             }
-                            Err(err) => error!("get_balance_update: {}", err),
                         }
-                    };
+                    }
+                    Err(err) => error!("reconnect snapshot: {}", err),
                 }
-                msg => anyhow::bail!("received unexpected message: {msg:?}"),
             }
         }
-        Ok(())
     });
 
-    Ok(rx)
+    Ok((
+        rx,
+        SubscriptionHandle {
+            cancellation_token,
+            task,
+        },
+    ))
+}
+
+/// Connects to the geyser endpoint and sends the subscription `request`.
+async fn connect_and_subscribe(
+    endpoint: &str,
+    x_token: Option<String>,
+    request: SubscribeRequest,
+    auth_hint: &str,
+) -> Result<(
+    impl Sink<SubscribeRequest, Error = futures::channel::mpsc::SendError> + Unpin,
+    impl Stream<Item = Result<SubscribeUpdate, Status>> + Unpin,
+)> {
+    let mut client = GeyserGrpcClient::build_from_shared(endpoint.to_owned())?
+        .x_token(x_token)?
+        .tls_config(ClientTlsConfig::new().with_native_roots())?
+        .connect()
+        .await
+        .map_err(|err| anyhow!("could not connect to {}: {}{}", endpoint, err, auth_hint))?;
+    let (mut subscribe_tx, stream) = client
+        .subscribe()
+        .await
+        .map_err(|err| anyhow!("could not subscribe to {}: {}{}", endpoint, err, auth_hint))?;
+
+    subscribe_tx.send(request).await?;
+
+    Ok((subscribe_tx, stream))
+}
+
+/// Builds the geyser request of `subscribe_balance_udpates`. Geyser matches an account against
+/// all the fields of a filter, so the explicit accounts and the owner programs have separate
+/// filters for their updates to be combined, and `filters` only apply to the owner programs.
+fn subscribe_request(
+    accounts: &[Pubkey],
+    owner_programs: &[Pubkey],
+    filters: Vec<AccountFilter>,
+    commitment: CommitmentConfig,
+) -> SubscribeRequest {
+    let mut account_filters = maplit::hashmap! {
+        "client".to_owned() => SubscribeRequestFilterAccounts {
+            nonempty_txn_signature: None,
+            account: accounts.iter().map(|account| account.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+        }
+    };
+    if owner_programs.is_empty() && !filters.is_empty() {
+        warn!("account filters are ignored without owner programs");
+    }
+    if !owner_programs.is_empty() {
+        account_filters.insert(
+            "owner_programs".to_owned(),
+            SubscribeRequestFilterAccounts {
+                nonempty_txn_signature: None,
+                account: vec![],
+                owner: owner_programs
+                    .iter()
+                    .map(|program| program.to_string())
+                    .collect(),
+                filters: filters
+                    .into_iter()
+                    .map(SubscribeRequestFilterAccountsFilter::from)
+                    .collect(),
+            },
+        );
+    }
+
+    SubscribeRequest {
+        accounts: account_filters,
+        commitment: Some(grpc_commitment_level(commitment) as i32),
+        ..Default::default()
+    }
+}
+
+fn grpc_commitment_level(commitment: CommitmentConfig) -> CommitmentLevel {
+    if commitment.is_finalized() {
+        CommitmentLevel::Finalized
+    } else if commitment.is_confirmed() {
+        CommitmentLevel::Confirmed
+    } else {
+        CommitmentLevel::Processed
+    }
+}
+
+fn requires_x_token(endpoint: &str) -> bool {
+    X_TOKEN_REQUIRED_HOSTS
+        .iter()
+        .any(|host| endpoint.contains(host))
+}
+
+/// Forwards the updates of several receivers into a single one.
+/// The merged receiver closes once all the input receivers are closed.
+pub fn merge_balance_receivers(
+    receivers: Vec<mpsc::Receiver<BalanceUpdate>>,
+) -> mpsc::Receiver<BalanceUpdate> {
+    let (tx, rx) = mpsc::channel::<BalanceUpdate>(1024 * 1024);
+
+    for mut receiver in receivers {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(balance_update) = receiver.recv().await {
+                if tx.send(balance_update).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    rx
+}
+
+/// Fetches the current state of `accounts` through RPC and returns it as `BalanceUpdate`s
+/// flagged with `UpdateSource::Snapshot`, to correct a consumer's view after a gap in the
+/// stream. Accounts that do not exist are skipped.
+pub async fn snapshot_balance_updates(
+    rpc: &RpcClient,
+    accounts: &[Pubkey],
+    commitment: CommitmentConfig,
+) -> Result<Vec<BalanceUpdate>> {
+    let mut balance_updates = vec![];
+    for chunk in accounts.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let response = rpc
+            .get_multiple_accounts_with_commitment(chunk, commitment)
+            .await
+            .map_err(|err| anyhow!("get_multiple_accounts: {}", err))?;
+        let slot = response.context.slot;
+
+        for (pubkey, account) in chunk.iter().zip(response.value) {
+            let Some(account) = account else {
+                debug!("snapshot account {} not found", pubkey);
+                continue;
+            };
+            match balance_update_from_account(
+                *pubkey,
+                account.owner,
+                account.lamports,
+                &account.data,
+                slot,
+                0,
+                UpdateSource::Snapshot,
+            ) {
+                Ok(balance_update) => balance_updates.push(balance_update),
+                Err(err) => error!("snapshot balance update for {}: {}", pubkey, err),
+            }
+        }
+    }
+
+    Ok(balance_updates)
 }
 
 fn get_balance_update(account: SubscribeUpdateAccountInfo, slot: u64) -> Result<BalanceUpdate> {
@@ -129,37 +539,161 @@ fn get_balance_update(account: SubscribeUpdateAccountInfo, slot: u64) -> Result<
         Pubkey::try_from(account.pubkey.clone()).map_err(|_| anyhow!("pubkey try_from"))?;
     let owner_pubkey =
         Pubkey::try_from(account.owner.clone()).map_err(|_| anyhow!("pubkey try_from"))?;
+    let write_version = account.write_version;
+
+    balance_update_from_account(
+        account_pubkey,
+        owner_pubkey,
+        account.lamports,
+        &account.data,
+        slot,
+        write_version,
+        UpdateSource::Stream,
+    )
+}
 
+fn balance_update_from_account(
+    account_pubkey: Pubkey,
+    owner_pubkey: Pubkey,
+    lamports: u64,
+    data: &[u8],
+    slot: u64,
+    write_version: u64,
+    source: UpdateSource,
+) -> Result<BalanceUpdate> {
     if owner_pubkey == system_program::id() {
         Ok(BalanceUpdate {
             is_native: true,
             pubkey: account_pubkey,
             mint: None,
-            amount: account.lamports,
+            owner: account_pubkey,
+            amount: lamports,
+            previous_amount: None,
             slot,
+            write_version,
+            source,
         })
     } else if owner_pubkey == spl_token::id() {
-        let account_state = spl_token::state::Account::unpack_from_slice(account.data.as_slice())
-            .map_err(|_| anyhow!("account unpack_from_slice"))?;
+        let account_state = spl_token::state::Account::unpack(data)
+            .map_err(|err| anyhow!("account unpack: {}", err))?;
         Ok(BalanceUpdate {
             is_native: false,
             pubkey: account_pubkey,
             mint: Some(account_state.mint),
+            owner: account_state.owner,
             amount: account_state.amount,
+            previous_amount: None,
             slot,
+            write_version,
+            source,
         })
     } else if owner_pubkey == spl_token_2022::id() {
-        let account_state =
-            spl_token_2022::state::Account::unpack_from_slice(account.data.as_slice())
-                .map_err(|_| anyhow!("account2022 unpack_from_slice"))?;
+        // Token-2022 accounts with extensions are larger than the base account.
+        let account_state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(data)
+            .map_err(|err| anyhow!("account2022 unpack: {}", err))?;
         Ok(BalanceUpdate {
             is_native: false,
             pubkey: account_pubkey,
-            mint: Some(account_state.mint),
-            amount: account_state.amount,
+            mint: Some(account_state.base.mint),
+            owner: account_state.base.owner,
+            amount: account_state.base.amount,
+            previous_amount: None,
             slot,
+            write_version,
+            source,
         })
     } else {
         Err(anyhow!("unexpected account owner {:?}", owner_pubkey))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spl_token_2022::{
+        extension::{
+            transfer_fee::TransferFeeAmount, BaseStateWithExtensionsMut, ExtensionType,
+            StateWithExtensionsMut,
+        },
+        state::{Account, AccountState},
+    };
+
+    #[test]
+    fn owner_programs_have_their_own_filter() {
+        let account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let request = subscribe_request(
+            &[account],
+            &[spl_token::id()],
+            vec![
+                AccountFilter::Datasize(165),
+                AccountFilter::Memcmp {
+                    offset: 32,
+                    bytes: owner.to_bytes().to_vec(),
+                },
+            ],
+            CommitmentConfig::confirmed(),
+        );
+
+        assert_eq!(request.accounts.len(), 2);
+        let client = &request.accounts["client"];
+        assert_eq!(client.account, vec![account.to_string()]);
+        assert!(client.owner.is_empty());
+        assert!(client.filters.is_empty());
+        let owner_programs = &request.accounts["owner_programs"];
+        assert!(owner_programs.account.is_empty());
+        assert_eq!(owner_programs.owner, vec![spl_token::id().to_string()]);
+        assert_eq!(owner_programs.filters.len(), 2);
+    }
+
+    /// Token-2022 account data with a `TransferFeeAmount` extension, as stored on chain for
+    /// mints charging transfer fees.
+    fn token_2022_account_with_transfer_fee(mint: Pubkey, owner: Pubkey, amount: u64) -> Vec<u8> {
+        let account_len = ExtensionType::try_calculate_account_len::<Account>(&[
+            ExtensionType::TransferFeeAmount,
+        ])
+        .unwrap();
+        let mut data = vec![0u8; account_len];
+        let mut state = StateWithExtensionsMut::<Account>::unpack_uninitialized(&mut data).unwrap();
+        state.base = Account {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..Default::default()
+        };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        let transfer_fee_amount = state.init_extension::<TransferFeeAmount>(true).unwrap();
+        transfer_fee_amount.withheld_amount = 7.into();
+        data
+    }
+
+    #[test]
+    fn decodes_token_2022_account_with_transfer_fee_extension() {
+        let pubkey = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let data = token_2022_account_with_transfer_fee(mint, owner, 1_000);
+        assert!(data.len() > Account::LEN);
+
+        let balance_update = balance_update_from_account(
+            pubkey,
+            spl_token_2022::id(),
+            2_039_280,
+            &data,
+            10,
+            3,
+            UpdateSource::Stream,
+        )
+        .unwrap();
+
+        assert!(!balance_update.is_native);
+        assert_eq!(balance_update.pubkey, pubkey);
+        assert_eq!(balance_update.mint, Some(mint));
+        assert_eq!(balance_update.owner, owner);
+        assert_eq!(balance_update.amount, 1_000);
+        assert_eq!(balance_update.slot, 10);
+        assert_eq!(balance_update.write_version, 3);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use solana_sdk::pubkey::Pubkey;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::subscribe::BalanceUpdate;
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    /// Latest update applied for each account.
+    accounts: HashMap<Pubkey, BalanceUpdate>,
+    /// Token accounts of each mint.
+    mint_accounts: HashMap<Pubkey, HashSet<Pubkey>>,
+}
+
+/// Latest balance of each account, fed by a stream of `BalanceUpdate`s.
+/// Updates older than the last one applied to the same account, by slot then write version,
+/// are ignored.
+#[derive(Default)]
+pub struct BalanceTracker {
+    state: Arc<RwLock<TrackerState>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl BalanceTracker {
+    /// Creates a tracker fed only through `apply`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the updates received on `rx` in the background until the receiver is closed
+    /// or the tracker is dropped.
+    pub fn spawn(mut rx: mpsc::Receiver<BalanceUpdate>) -> Self {
+        let state = Arc::new(RwLock::new(TrackerState::default()));
+
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {
+            while let Some(balance_update) = rx.recv().await {
+                apply_update(&task_state, balance_update);
+            }
+        });
+
+        Self {
+            state,
+            task: Some(task),
+        }
+    }
+
+    /// Applies an update, returning false if it is older than the one already applied.
+    pub fn apply(&self, balance_update: BalanceUpdate) -> bool {
+        apply_update(&self.state, balance_update)
+    }
+
+    /// Latest amount of an account.
+    pub fn get(&self, pubkey: &Pubkey) -> Option<u64> {
+        let state = self.state.read().unwrap();
+        state
+            .accounts
+            .get(pubkey)
+            .map(|balance_update| balance_update.amount)
+    }
+
+    /// Latest amounts of the token accounts of a mint.
+    pub fn get_by_mint(&self, mint: &Pubkey) -> HashMap<Pubkey, u64> {
+        let state = self.state.read().unwrap();
+        let Some(accounts) = state.mint_accounts.get(mint) else {
+            return HashMap::new();
+        };
+        accounts
+            .iter()
+            .filter_map(|pubkey| {
+                state
+                    .accounts
+                    .get(pubkey)
+                    .map(|balance_update| (*pubkey, balance_update.amount))
+            })
+            .collect()
+    }
+
+    /// Latest amounts of all the tracked accounts.
+    pub fn snapshot(&self) -> HashMap<Pubkey, u64> {
+        let state = self.state.read().unwrap();
+        state
+            .accounts
+            .iter()
+            .map(|(pubkey, balance_update)| (*pubkey, balance_update.amount))
+            .collect()
+    }
+}
+
+impl Drop for BalanceTracker {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+fn apply_update(state: &RwLock<TrackerState>, balance_update: BalanceUpdate) -> bool {
+    let mut state = state.write().unwrap();
+    if let Some(current) = state.accounts.get(&balance_update.pubkey) {
+        if (balance_update.slot, balance_update.write_version)
+            < (current.slot, current.write_version)
+        {
+            return false;
+        }
+    }
+
+    if let Some(mint) = balance_update.mint {
+        state
+            .mint_accounts
+            .entry(mint)
+            .or_default()
+            .insert(balance_update.pubkey);
+    }
+    state.accounts.insert(balance_update.pubkey, balance_update);
+    true
+}
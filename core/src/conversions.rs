@@ -1,13 +1,115 @@
 use anyhow::{anyhow, Result};
 use base64::{decode, encode};
-use solana_sdk::{bs58, transaction::VersionedTransaction};
+use solana_sdk::{
+    bs58,
+    hash::Hash,
+    message::VersionedMessage,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::VersionedTransaction,
+};
 
+/// Largest `decimals` whose unit scale `10^decimals` fits in a `u128`.
+pub const MAX_DECIMALS: u32 = 38;
+
+/// Number of units in one ui unit, `None` above `MAX_DECIMALS`.
+fn units_per_ui_number(decimals: u32) -> Option<u128> {
+    10u128.checked_pow(decimals)
+}
+
+/// Amounts above `u64::MAX` units saturate, as does any positive amount with more than
+/// `MAX_DECIMALS` decimals. `f64` only has 53 bits of precision and the result is truncated,
+/// so large amounts can be off by a few units: use `ui_str_to_units_checked` for exact amounts.
 pub fn ui_number_to_units(ui_number: f64, decimals: u32) -> u64 {
-    (ui_number * 10i32.pow(decimals) as f64) as u64
+    match units_per_ui_number(decimals) {
+        Some(scale) => (ui_number * scale as f64) as u64,
+        None if ui_number > 0.0 => u64::MAX,
+        None => 0,
+    }
+}
+
+/// Same as `ui_number_to_units`, erroring on negative or non-finite inputs, on more than
+/// `MAX_DECIMALS` decimals, on amounts above `u64::MAX` units and when a non-zero input
+/// truncates to zero units.
+pub fn ui_number_to_units_checked(ui_number: f64, decimals: u32) -> Result<u64> {
+    if !ui_number.is_finite() || ui_number < 0.0 {
+        return Err(anyhow!(
+            "ui_number_to_units_checked invalid ui number {}",
+            ui_number
+        ));
+    }
+    let scale = units_per_ui_number(decimals).ok_or(anyhow!(
+        "ui_number_to_units_checked unsupported decimals {}",
+        decimals
+    ))?;
+    let scaled = ui_number * scale as f64;
+    if scaled >= u64::MAX as f64 {
+        return Err(anyhow!(
+            "ui_number_to_units_checked {} with {} decimals overflows u64",
+            ui_number,
+            decimals
+        ));
+    }
+    let units = scaled as u64;
+    if units == 0 && ui_number > 0.0 {
+        return Err(anyhow!(
+            "ui_number_to_units_checked {} is below the smallest unit with {} decimals",
+            ui_number,
+            decimals
+        ));
+    }
+    Ok(units)
 }
 
+/// Exact conversion of a decimal string such as `"1000000.123456789"` to units, erroring on
+/// malformed input, on more fractional digits than `decimals` and on amounts above `u64::MAX`.
+pub fn ui_str_to_units_checked(ui_amount: &str, decimals: u32) -> Result<u64> {
+    let (integer_part, fractional_part) = ui_amount
+        .trim()
+        .split_once('.')
+        .unwrap_or((ui_amount.trim(), ""));
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (integer_part.is_empty() && fractional_part.is_empty())
+        || !is_digits(integer_part)
+        || !is_digits(fractional_part)
+    {
+        return Err(anyhow!(
+            "ui_str_to_units_checked invalid amount {}",
+            ui_amount
+        ));
+    }
+    if fractional_part.len() > decimals as usize {
+        return Err(anyhow!(
+            "ui_str_to_units_checked {} has more than {} decimals",
+            ui_amount,
+            decimals
+        ));
+    }
+
+    let digits = format!(
+        "{}{}{}",
+        integer_part,
+        fractional_part,
+        "0".repeat(decimals as usize - fractional_part.len())
+    );
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits.parse::<u64>().map_err(|_| {
+        anyhow!(
+            "ui_str_to_units_checked {} with {} decimals overflows u64",
+            ui_amount,
+            decimals
+        )
+    })
+}
+
+/// Amounts with more than `MAX_DECIMALS` decimals are below `f64` precision and convert to 0.
 pub fn units_to_ui_number(units: u64, decimals: u32) -> f64 {
-    units as f64 / 10i32.pow(decimals) as f64
+    units_per_ui_number(decimals).map_or(0.0, |scale| units as f64 / scale as f64)
 }
 
 pub fn base64_to_bytes(string_base64: &String) -> Result<Vec<u8>> {
@@ -18,6 +120,16 @@ pub fn bytes_to_base64(bytes: &Vec<u8>) -> String {
     encode(bytes)
 }
 
+pub fn base58_to_bytes(string_base58: &str) -> Result<Vec<u8>> {
+    bs58::decode(string_base58)
+        .into_vec()
+        .map_err(|err| anyhow!("base58_to_bytes {}", err))
+}
+
+pub fn bytes_to_base58(bytes: &[u8]) -> String {
+    bs58::encode(bytes).into_string()
+}
+
 pub fn tx_from_bytes(tx_bytes: &Vec<u8>) -> Result<VersionedTransaction> {
     Ok(bincode::deserialize(&tx_bytes).map_err(|err| anyhow!("tx_from_bytes {}", err))?)
 }
@@ -37,13 +149,109 @@ pub fn tx_to_base64(tx: &VersionedTransaction) -> Result<String> {
 }
 
 pub fn tx_from_base58(tx_base58: &String) -> Result<VersionedTransaction> {
-    let tx_bytes = bs58::decode(tx_base58)
-        .into_vec()
-        .map_err(|err| anyhow!("tx_from_base58 {}", err))?;
+    let tx_bytes = base58_to_bytes(tx_base58)?;
     tx_from_bytes(&tx_bytes)
 }
 
 pub fn tx_to_base58(tx: &VersionedTransaction) -> Result<String> {
     let tx_bytes = tx_to_bytes(tx)?;
-    Ok(bs58::encode(tx_bytes).into_string())
+    Ok(bytes_to_base58(&tx_bytes))
+}
+
+/// Signature of the fee payer, which identifies the transaction.
+pub fn tx_signature(tx: &VersionedTransaction) -> Option<Signature> {
+    tx.signatures.first().copied()
+}
+
+/// Fee payer of the transaction, its first static account key.
+pub fn tx_fee_payer(tx: &VersionedTransaction) -> Option<Pubkey> {
+    tx.message.static_account_keys().first().copied()
+}
+
+/// Size of the transaction once serialized, in bytes.
+pub fn tx_serialized_len(tx: &VersionedTransaction) -> Result<usize> {
+    let len = bincode::serialized_size(tx).map_err(|err| anyhow!("tx_serialized_len {}", err))?;
+    Ok(len as usize)
+}
+
+/// Whether the serialized transaction fits in a packet, i.e. is at most `PACKET_DATA_SIZE`
+/// (1232) bytes.
+pub fn tx_fits(tx: &VersionedTransaction) -> Result<bool> {
+    Ok(tx_serialized_len(tx)? <= PACKET_DATA_SIZE)
+}
+
+/// Replaces the recent blockhash of a message, e.g. decoded with `tx_from_base64`, before
+/// signing it again.
+pub fn replace_recent_blockhash(message: &mut VersionedMessage, recent_blockhash: Hash) {
+    message.set_recent_blockhash(recent_blockhash);
+}
+
+/// Signs a message with the keypairs of all its required signers, erroring with the list of
+/// the missing ones.
+pub fn sign_versioned_transaction(
+    message: VersionedMessage,
+    signers: &[&Keypair],
+) -> Result<VersionedTransaction> {
+    let num_required_signatures = message.header().num_required_signatures as usize;
+    let required_signers: Vec<(Pubkey, Option<&&Keypair>)> = message
+        .static_account_keys()
+        .iter()
+        .take(num_required_signatures)
+        .map(|key| (*key, signers.iter().find(|signer| signer.pubkey() == *key)))
+        .collect();
+
+    let missing_signers: Vec<String> = required_signers
+        .iter()
+        .filter(|(_, signer)| signer.is_none())
+        .map(|(key, _)| key.to_string())
+        .collect();
+    if !missing_signers.is_empty() {
+        return Err(anyhow!(
+            "sign_versioned_transaction missing signers {}",
+            missing_signers.join(", ")
+        ));
+    }
+
+    let message_data = message.serialize();
+    let signatures = required_signers
+        .iter()
+        .filter_map(|(_, signer)| signer.map(|signer| signer.sign_message(&message_data)))
+        .collect();
+
+    Ok(VersionedTransaction {
+        signatures,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ui_number_to_units_scales_by_decimals() {
+        assert_eq!(ui_number_to_units(1.5, 0), 1);
+        assert_eq!(ui_number_to_units(1.5, 9), 1_500_000_000);
+        assert_eq!(ui_number_to_units(1.5, 12), 1_500_000_000_000);
+        assert_eq!(
+            ui_number_to_units_checked(1.5, 12).unwrap(),
+            1_500_000_000_000
+        );
+    }
+
+    #[test]
+    fn units_to_ui_number_scales_by_decimals() {
+        assert_eq!(units_to_ui_number(42, 0), 42.0);
+        assert_eq!(units_to_ui_number(1_500_000_000, 9), 1.5);
+        assert_eq!(units_to_ui_number(1_500_000_000_000, 12), 1.5);
+    }
+
+    #[test]
+    fn large_decimals_do_not_panic() {
+        assert_eq!(ui_number_to_units(1.0, 20), u64::MAX);
+        assert_eq!(ui_number_to_units(1.0, u32::MAX), u64::MAX);
+        assert!(ui_number_to_units_checked(1.0, 20).is_err());
+        assert!(ui_number_to_units_checked(1.0, MAX_DECIMALS + 1).is_err());
+        assert_eq!(units_to_ui_number(u64::MAX, u32::MAX), 0.0);
+    }
 }
@@ -1,5 +1,8 @@
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
 
 pub fn datetime_now() -> DateTime<Utc> {
     DateTime::from(Utc::now())
@@ -14,3 +17,83 @@ pub fn datetime_from_timestamp_sec(timestamp_sec: i64) -> Result<DateTime<Utc>>
         )),
     }
 }
+
+pub fn datetime_from_timestamp_millis(timestamp_millis: i64) -> Result<DateTime<Utc>> {
+    match DateTime::from_timestamp_millis(timestamp_millis) {
+        Some(res) => Ok(res),
+        None => Err(anyhow!(
+            "could not convert timestamp_millis {} to date time",
+            timestamp_millis
+        )),
+    }
+}
+
+pub fn datetime_to_timestamp_sec(datetime: DateTime<Utc>) -> i64 {
+    datetime.timestamp()
+}
+
+pub fn timestamp_sec_now() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// Parses durations such as "500ms", "3s", "5m" or "1h".
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let unit_start = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or(anyhow!("parse_duration missing unit in {:?}", duration))?;
+    let (value, unit) = duration.split_at(unit_start);
+    let value = value
+        .parse::<u64>()
+        .map_err(|err| anyhow!("parse_duration invalid value in {:?}: {}", duration, err))?;
+
+    let seconds_per_unit = match unit {
+        "ms" => return Ok(Duration::from_millis(value)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => {
+            return Err(anyhow!(
+                "parse_duration unknown unit {:?} in {:?}",
+                unit,
+                duration
+            ))
+        }
+    };
+    value
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or(anyhow!("parse_duration {:?} overflows", duration))
+}
+
+/// Deserializes a `Duration` written as in `parse_duration`, to be used with
+/// `#[serde(deserialize_with = "deserialize_duration")]`.
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Duration, D::Error> {
+    let duration = String::deserialize(deserializer)?;
+    parse_duration(&duration).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("3s").unwrap(), Duration::from_secs(3));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(5 * 60));
+        assert_eq!(
+            parse_duration(" 1h ").unwrap(),
+            Duration::from_secs(60 * 60)
+        );
+    }
+
+    #[test]
+    fn parse_duration_rejects_unknown_or_missing_unit() {
+        assert!(parse_duration("2d").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+}
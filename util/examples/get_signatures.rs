@@ -13,7 +13,7 @@ async fn get_signatures(rpc: &RpcClient) -> Vec<Signature> {
     let since = datetime_now() - chrono::Duration::seconds(45);
 
     println!("requesting signatures since {:?}", since);
-    let signatures = get_signatures_since_time(
+    let page = get_signatures_since_time(
         rpc,
         solana_sdk::pubkey!("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4"),
         since.timestamp(),
@@ -22,12 +22,14 @@ async fn get_signatures(rpc: &RpcClient) -> Vec<Signature> {
             limit: 1000,
             commitment: CommitmentConfig::finalized(),
             log_progress: true,
+            ..Default::default()
         },
     )
     .await
     .unwrap();
+    let signatures = page.signatures;
 
-    println!("found {}", signatures.len());
+    println!("found {} (complete: {})", signatures.len(), page.complete);
     let oldest_block_time = signatures[signatures.len() - 1].block_time.unwrap();
     println!(
         "oldest signature time: {:?}",
@@ -63,6 +65,7 @@ async fn main() {
             encoding: solana_transaction_status::UiTransactionEncoding::JsonParsed,
             commitment: CommitmentConfig::finalized(),
             log_progress: true,
+            ..Default::default()
         },
     )
     .await;
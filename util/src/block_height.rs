@@ -0,0 +1,76 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Latest block height shared between many readers, so that concurrent confirmers
+/// do not each poll `getBlockHeight`.
+/// The height is either polled by a background task or pushed with `update`,
+/// e.g. from a slot subscription.
+pub struct BlockHeightTracker {
+    height: Arc<AtomicU64>,
+    poll_task: Option<JoinHandle<()>>,
+}
+
+impl BlockHeightTracker {
+    /// Creates a tracker fed only through `update`.
+    pub fn new(initial_height: u64) -> Self {
+        Self {
+            height: Arc::new(AtomicU64::new(initial_height)),
+            poll_task: None,
+        }
+    }
+
+    /// Fetches the current block height, then refreshes it every `interval` in the background
+    /// until the tracker is dropped.
+    pub async fn spawn_polling(rpc_client: Arc<RpcClient>, interval: Duration) -> Result<Self> {
+        let initial_height = rpc_client
+            .get_block_height()
+            .await
+            .map_err(|err| anyhow!("get_block_height: {}", err))?;
+        let height = Arc::new(AtomicU64::new(initial_height));
+
+        let task_height = height.clone();
+        let poll_task = tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            loop {
+                timer.tick().await;
+                match rpc_client.get_block_height().await {
+                    Ok(block_height) => {
+                        task_height.fetch_max(block_height, Ordering::Relaxed);
+                    }
+                    Err(err) => warn!("BlockHeightTracker get_block_height: {}", err),
+                }
+            }
+        });
+
+        Ok(Self {
+            height,
+            poll_task: Some(poll_task),
+        })
+    }
+
+    /// Latest known block height.
+    pub fn height(&self) -> u64 {
+        self.height.load(Ordering::Relaxed)
+    }
+
+    /// Records a new block height, ignoring heights older than the current one.
+    pub fn update(&self, block_height: u64) {
+        self.height.fetch_max(block_height, Ordering::Relaxed);
+    }
+}
+
+impl Drop for BlockHeightTracker {
+    fn drop(&mut self) {
+        if let Some(poll_task) = self.poll_task.take() {
+            poll_task.abort();
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use tokio::runtime::Runtime;
+
+use crate::{
+    jupiter_client::{GetQuoteParams, GetSwapParams, JupiterClient, QuoteResponse, SwapResponse},
+    token::get_spl_balances,
+};
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Runtime shared by all the blocking wrappers, created on first use.
+/// The wrappers must not be called from within an async context, as `block_on` panics there.
+fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("blocking runtime")
+    })
+}
+
+impl JupiterClient {
+    /// Blocking version of `get_quote`.
+    pub fn get_quote_blocking(&self, params: GetQuoteParams) -> Result<QuoteResponse> {
+        runtime().block_on(self.get_quote(params))
+    }
+
+    /// Blocking version of `get_swap_transaction`.
+    pub fn get_swap_transaction_blocking(&self, params: GetSwapParams) -> Result<SwapResponse> {
+        runtime().block_on(self.get_swap_transaction(params))
+    }
+}
+
+/// Blocking version of `get_spl_balances`.
+pub fn get_spl_balances_blocking(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+) -> HashMap<Pubkey, Result<f64>> {
+    runtime().block_on(get_spl_balances(rpc, owner, mints))
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+
+/// Error returned by long-running helpers when their `CancellationToken` is cancelled,
+/// retrievable with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Returns a `Cancelled` error if the token has been cancelled.
+pub(crate) fn check_cancelled(token: &Option<CancellationToken>) -> Result<()> {
+    match token {
+        Some(token) if token.is_cancelled() => Err(Cancelled.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Sleeps for `duration`, returning a `Cancelled` error early if the token is cancelled.
+pub(crate) async fn sleep_or_cancelled(
+    duration: std::time::Duration,
+    token: &Option<CancellationToken>,
+) -> Result<()> {
+    match token {
+        Some(token) => tokio::select! {
+            _ = token.cancelled() => Err(Cancelled.into()),
+            _ = tokio::time::sleep(duration) => Ok(()),
+        },
+        None => {
+            tokio::time::sleep(duration).await;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// Builds a header map from name/value pairs, failing on invalid header names or values.
+pub(crate) fn header_map(headers: &[(String, String)]) -> Result<HeaderMap> {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|err| anyhow!("invalid header name {}: {}", name, err))?;
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|err| anyhow!("invalid header value for {}: {}", name, err))?;
+        header_map.insert(header_name, header_value);
+    }
+    Ok(header_map)
+}
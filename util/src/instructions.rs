@@ -0,0 +1,312 @@
+use anyhow::{anyhow, Result};
+use solana_sdk::{bs58, pubkey::Pubkey};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+    EncodedTransaction, UiInstruction, UiMessage, UiParsedInstruction,
+    UiPartiallyDecodedInstruction,
+};
+
+use crate::parse::parse_pubkey;
+
+/// Instruction of a confirmed transaction with its program id and accounts resolved.
+#[derive(Debug, Clone)]
+pub struct DecodedInstruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<Pubkey>,
+    pub data: Vec<u8>,
+    /// Index of the top-level instruction this instruction belongs to.
+    pub outer_index: usize,
+    /// Index within the inner instructions of `outer_index`, `None` for top-level instructions.
+    pub inner_index: Option<usize>,
+}
+
+/// Instruction as found in the transaction, referencing accounts by index once compiled.
+enum RawInstruction {
+    Compiled {
+        program_id_index: u8,
+        accounts: Vec<u8>,
+        data: Vec<u8>,
+    },
+    Resolved {
+        program_id: Pubkey,
+        accounts: Vec<Pubkey>,
+        data: Vec<u8>,
+    },
+}
+
+/// Extracts the top-level and inner instructions of a transaction, in execution order.
+/// With the `JsonParsed` encoding, instructions of programs known to the RPC are fully parsed
+/// and lose their raw data, so they are rejected: prefer the `Json` or `Base64` encodings.
+pub fn decode_instructions(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<Vec<DecodedInstruction>> {
+    // Parsed messages already list the accounts loaded from address lookup tables.
+    let mut includes_loaded_addresses = false;
+    let (mut account_keys, outer_instructions) = match &tx.transaction.transaction {
+        EncodedTransaction::Json(ui_tx) => match &ui_tx.message {
+            UiMessage::Raw(message) => {
+                let account_keys = message
+                    .account_keys
+                    .iter()
+                    .map(|key| parse_pubkey(key))
+                    .collect::<Result<Vec<_>>>()?;
+                let instructions = message
+                    .instructions
+                    .iter()
+                    .map(|ix| {
+                        Ok(RawInstruction::Compiled {
+                            program_id_index: ix.program_id_index,
+                            accounts: ix.accounts.clone(),
+                            data: decode_data(&ix.data)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                (account_keys, instructions)
+            }
+            UiMessage::Parsed(message) => {
+                includes_loaded_addresses = true;
+                let account_keys = message
+                    .account_keys
+                    .iter()
+                    .map(|account| parse_pubkey(&account.pubkey))
+                    .collect::<Result<Vec<_>>>()?;
+                let instructions = message
+                    .instructions
+                    .iter()
+                    .map(raw_instruction)
+                    .collect::<Result<Vec<_>>>()?;
+                (account_keys, instructions)
+            }
+        },
+        EncodedTransaction::Accounts(_) => {
+            return Err(anyhow!(
+                "decode_instructions accounts encoding is not supported"
+            ))
+        }
+        encoded_tx => {
+            let versioned_tx = encoded_tx
+                .decode()
+                .ok_or(anyhow!("decode_instructions could not decode transaction"))?;
+            let account_keys = versioned_tx.message.static_account_keys().to_vec();
+            let instructions = versioned_tx
+                .message
+                .instructions()
+                .iter()
+                .map(|ix| RawInstruction::Compiled {
+                    program_id_index: ix.program_id_index,
+                    accounts: ix.accounts.clone(),
+                    data: ix.data.clone(),
+                })
+                .collect();
+            (account_keys, instructions)
+        }
+    };
+
+    let meta = tx.transaction.meta.as_ref();
+
+    // Accounts loaded from address lookup tables follow the static keys,
+    // writable ones first.
+    if let (false, Some(OptionSerializer::Some(loaded_addresses))) = (
+        includes_loaded_addresses,
+        meta.map(|meta| &meta.loaded_addresses),
+    ) {
+        for key in loaded_addresses
+            .writable
+            .iter()
+            .chain(loaded_addresses.readonly.iter())
+        {
+            account_keys.push(parse_pubkey(key)?);
+        }
+    }
+
+    let mut inner_instructions: Vec<Vec<RawInstruction>> =
+        outer_instructions.iter().map(|_| vec![]).collect();
+    if let Some(OptionSerializer::Some(inner)) = meta.map(|meta| &meta.inner_instructions) {
+        for inner_instruction in inner {
+            let instructions = inner_instructions
+                .get_mut(inner_instruction.index as usize)
+                .ok_or(anyhow!(
+                    "inner instructions for unknown instruction {}",
+                    inner_instruction.index
+                ))?;
+            for ix in &inner_instruction.instructions {
+                instructions.push(raw_instruction(ix)?);
+            }
+        }
+    }
+
+    let mut decoded = vec![];
+    for (outer_index, (outer, inner)) in outer_instructions
+        .iter()
+        .zip(inner_instructions.iter())
+        .enumerate()
+    {
+        decoded.push(resolve_instruction(
+            outer,
+            &account_keys,
+            outer_index,
+            None,
+        )?);
+        for (inner_index, ix) in inner.iter().enumerate() {
+            decoded.push(resolve_instruction(
+                ix,
+                &account_keys,
+                outer_index,
+                Some(inner_index),
+            )?);
+        }
+    }
+
+    Ok(decoded)
+}
+
+fn raw_instruction(ix: &UiInstruction) -> Result<RawInstruction> {
+    match ix {
+        UiInstruction::Compiled(ix) => Ok(RawInstruction::Compiled {
+            program_id_index: ix.program_id_index,
+            accounts: ix.accounts.clone(),
+            data: decode_data(&ix.data)?,
+        }),
+        UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(
+            UiPartiallyDecodedInstruction {
+                program_id,
+                accounts,
+                data,
+                ..
+            },
+        )) => Ok(RawInstruction::Resolved {
+            program_id: parse_pubkey(program_id)?,
+            accounts: accounts
+                .iter()
+                .map(|key| parse_pubkey(key))
+                .collect::<Result<Vec<_>>>()?,
+            data: decode_data(data)?,
+        }),
+        UiInstruction::Parsed(UiParsedInstruction::Parsed(ix)) => Err(anyhow!(
+            "decode_instructions {} instruction is fully parsed, use the json or base64 encoding",
+            ix.program
+        )),
+    }
+}
+
+fn resolve_instruction(
+    ix: &RawInstruction,
+    account_keys: &[Pubkey],
+    outer_index: usize,
+    inner_index: Option<usize>,
+) -> Result<DecodedInstruction> {
+    let resolve = |idx: u8| {
+        account_keys
+            .get(idx as usize)
+            .copied()
+            .ok_or(anyhow!("account index {} out of bounds", idx))
+    };
+
+    let (program_id, accounts, data) = match ix {
+        RawInstruction::Compiled {
+            program_id_index,
+            accounts,
+            data,
+        } => (
+            resolve(*program_id_index)?,
+            accounts
+                .iter()
+                .map(|idx| resolve(*idx))
+                .collect::<Result<Vec<_>>>()?,
+            data,
+        ),
+        RawInstruction::Resolved {
+            program_id,
+            accounts,
+            data,
+        } => (*program_id, accounts.clone(), data),
+    };
+
+    Ok(DecodedInstruction {
+        program_id,
+        accounts,
+        data: data.clone(),
+        outer_index,
+        inner_index,
+    })
+}
+
+fn decode_data(data: &str) -> Result<Vec<u8>> {
+    bs58::decode(data)
+        .into_vec()
+        .map_err(|err| anyhow!("invalid instruction data: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn json_parsed_tx(
+        account_keys: &[Pubkey],
+        instructions: Vec<Value>,
+    ) -> EncodedConfirmedTransactionWithStatusMeta {
+        let account_keys: Vec<Value> = account_keys
+            .iter()
+            .map(|key| {
+                json!({
+                    "pubkey": key.to_string(),
+                    "writable": true,
+                    "signer": false,
+                    "source": "transaction",
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "slot": 1,
+            "transaction": {
+                "signatures": [],
+                "message": {
+                    "accountKeys": account_keys,
+                    "recentBlockhash": "11111111111111111111111111111111",
+                    "instructions": instructions,
+                },
+            },
+            "meta": null,
+            "blockTime": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_partially_decoded_json_parsed_instructions() {
+        let program_id = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let tx = json_parsed_tx(
+            &[account, program_id],
+            vec![json!({
+                "programId": program_id.to_string(),
+                "accounts": [account.to_string()],
+                "data": bs58::encode([1, 2, 3]).into_string(),
+                "stackHeight": null,
+            })],
+        );
+
+        let decoded = decode_instructions(&tx).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].program_id, program_id);
+        assert_eq!(decoded[0].accounts, vec![account]);
+        assert_eq!(decoded[0].data, vec![1, 2, 3]);
+        assert_eq!(decoded[0].inner_index, None);
+    }
+
+    #[test]
+    fn rejects_fully_parsed_instructions() {
+        let tx = json_parsed_tx(
+            &[Pubkey::new_unique()],
+            vec![json!({
+                "program": "spl-memo",
+                "programId": Pubkey::new_unique().to_string(),
+                "parsed": "memo",
+                "stackHeight": null,
+            })],
+        );
+
+        assert!(decode_instructions(&tx).is_err());
+    }
+}
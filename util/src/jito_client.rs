@@ -1,17 +1,33 @@
+use crate::block_height::BlockHeightTracker;
+use crate::http::header_map;
+use crate::parse::parse_signature;
 use anyhow::{anyhow, Result};
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
-use reqwest::Client;
+use reqwest::header::{HeaderValue, ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
 use solana_sdk::instruction::Instruction;
+use solana_sdk::native_token::sol_to_lamports;
 use solana_sdk::pubkey;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
 use solana_sdk::system_instruction::transfer;
 use solana_sdk::transaction::VersionedTransaction;
-use solana_trading_core::conversions::tx_to_base58;
-use std::time::Duration;
-use tracing::{error, info};
+use solana_trading_core::conversions::{tx_from_base58, tx_to_base58};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::cancel::{check_cancelled, sleep_or_cancelled};
 
 #[derive(Serialize)]
 struct JitoRequest {
@@ -60,7 +76,6 @@ struct JitoResponse<T> {
     error: Option<JitoResponseError>,
 }
 
-#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 struct JitoResponseError {
     code: i64,
@@ -68,6 +83,59 @@ struct JitoResponseError {
     // data: Option<String>,
 }
 
+/// Error returned by the Jito API, retrievable with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone)]
+pub enum JitoError {
+    /// HTTP 429 or the JSON-RPC rate limit error, callers should back off before retrying.
+    RateLimited(String),
+    /// The bundle has more transactions than allowed.
+    BundleTooLarge(String),
+    /// A transaction of the bundle is malformed, badly signed or fails simulation.
+    InvalidTransaction(String),
+    /// Any other JSON-RPC error.
+    Rpc { code: i64, message: String },
+}
+
+const JITO_RATE_LIMITED_CODE: i64 = -32097;
+const JITO_INVALID_PARAMS_CODE: i64 = -32602;
+
+impl From<JitoResponseError> for JitoError {
+    fn from(err: JitoResponseError) -> Self {
+        let message = err.message.to_lowercase();
+        match err.code {
+            JITO_RATE_LIMITED_CODE => JitoError::RateLimited(err.message),
+            _ if message.contains("rate limit") => JitoError::RateLimited(err.message),
+            JITO_INVALID_PARAMS_CODE
+                if message.contains("bundle") && message.contains("exceed") =>
+            {
+                JitoError::BundleTooLarge(err.message)
+            }
+            JITO_INVALID_PARAMS_CODE if message.contains("transaction") => {
+                JitoError::InvalidTransaction(err.message)
+            }
+            code => JitoError::Rpc {
+                code,
+                message: err.message,
+            },
+        }
+    }
+}
+
+impl fmt::Display for JitoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JitoError::RateLimited(message) => write!(f, "jito rate limited: {}", message),
+            JitoError::BundleTooLarge(message) => write!(f, "jito bundle too large: {}", message),
+            JitoError::InvalidTransaction(message) => {
+                write!(f, "jito invalid transaction: {}", message)
+            }
+            JitoError::Rpc { code, message } => write!(f, "jito error {}: {}", code, message),
+        }
+    }
+}
+
+impl std::error::Error for JitoError {}
+
 #[allow(dead_code)]
 #[derive(Deserialize)]
 struct JitoResponseContextValue<T> {
@@ -89,39 +157,463 @@ struct GetInflightBundleStatusesResponse {
     landed_slot: Option<u64>,
 }
 
-#[allow(dead_code)]
 #[derive(Deserialize)]
 struct GetBundleStatusesResponse {
     bundle_id: String,
     transactions: Vec<String>,
     slot: u64,
-    confirmation_status: String,
+    confirmation_status: BundleConfirmationStatus,
+}
+
+/// Inflight status of a bundle, as returned by `getInflightBundleStatuses`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InflightBundleStatus {
+    /// Unknown bundle, or not yet seen by the block engine.
+    Invalid,
+    Pending,
+    Failed,
+    Landed {
+        slot: Option<u64>,
+    },
+}
+
+impl InflightBundleStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            InflightBundleStatus::Failed | InflightBundleStatus::Landed { .. }
+        )
+    }
+}
+
+/// Commitment reached by a landed bundle.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleConfirmationStatus {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+/// Status of a landed bundle, as returned by `getBundleStatuses`.
+#[derive(Debug, Clone)]
+pub struct BundleStatus {
+    pub bundle_id: String,
+    pub slot: u64,
+    pub confirmation_status: BundleConfirmationStatus,
+    pub transactions: Vec<Signature>,
+}
+
+impl TryFrom<GetBundleStatusesResponse> for BundleStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: GetBundleStatusesResponse) -> Result<Self> {
+        let transactions = value
+            .transactions
+            .iter()
+            .map(|signature| parse_signature(signature))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            bundle_id: value.bundle_id,
+            slot: value.slot,
+            confirmation_status: value.confirmation_status,
+            transactions,
+        })
+    }
+}
+
+/// Timing of a bundle from submission to landing, used to correlate tip size with landing speed.
+#[derive(Debug, Clone)]
+pub struct BundleLandingReport {
+    pub bundle_id: String,
+    pub submit_slot: u64,
+    pub landed_slot: Option<u64>,
+    pub elapsed: Duration,
+    pub tip_lamports: u64,
+    /// Tip account found in the bundle, if any.
+    pub tip_account: Option<Pubkey>,
+}
+
+impl BundleLandingReport {
+    /// Number of slots between submission and landing, if the landed slot is known.
+    pub fn slots_elapsed(&self) -> Option<u64> {
+        self.landed_slot
+            .map(|landed_slot| landed_slot.saturating_sub(self.submit_slot))
+    }
+}
+
+/// Delay between two bundle status checks.
+#[derive(Debug, Clone)]
+pub enum PollSchedule {
+    Fixed(Duration),
+    /// Starts at `initial` and multiplies the delay by `factor` after every poll, up to `max`.
+    Exponential {
+        initial: Duration,
+        max: Duration,
+        factor: f64,
+    },
+}
+
+impl PollSchedule {
+    /// Delay to wait after the poll number `poll`, starting from 0.
+    pub fn delay(&self, poll: u32) -> Duration {
+        match self {
+            PollSchedule::Fixed(period) => *period,
+            PollSchedule::Exponential {
+                initial,
+                max,
+                factor,
+            } => {
+                let delay = initial.as_secs_f64() * factor.powi(poll as i32);
+                Duration::from_secs_f64(delay.min(max.as_secs_f64()).max(0.0))
+            }
+        }
+    }
+}
+
+/// How `send_and_confirm_bundle` decides that a bundle landed.
+#[derive(Debug, Clone, Default)]
+pub enum ConfirmationStrategy {
+    /// Waits for `getInflightBundleStatuses` to report the bundle as landed, the fastest.
+    #[default]
+    Inflight,
+    /// Waits for the bundle to land, then for `getBundleStatuses` to report it finalized.
+    InflightThenFinalized,
+    /// Waits for the signatures of all the bundle transactions to be confirmed through RPC.
+    Signatures,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfirmBundleConfig {
+    pub strategy: ConfirmationStrategy,
+    pub poll_schedule: PollSchedule,
+    /// Wall-clock limit on the confirmation, enforced even if the block height stalls.
+    pub timeout: Option<Duration>,
+    /// Checked between polls, returning a `Cancelled` error when cancelled.
+    pub cancellation_token: Option<CancellationToken>,
+    /// Gives up early on bundles that were accepted but silently dropped.
+    pub drop_detection: Option<DropDetection>,
+    /// Maximum number of status polls before giving up.
+    pub max_polls: Option<u32>,
+}
+
+/// Considers a bundle dropped when its inflight status stays invalid or unknown for more than
+/// `max_invalid_polls` consecutive polls, once the block height advanced by at least
+/// `min_block_height_advance` since the first poll.
+#[derive(Debug, Clone)]
+pub struct DropDetection {
+    pub max_invalid_polls: u32,
+    pub min_block_height_advance: u64,
+}
+
+impl Default for ConfirmBundleConfig {
+    fn default() -> Self {
+        Self {
+            strategy: ConfirmationStrategy::Inflight,
+            poll_schedule: PollSchedule::Fixed(Duration::from_secs(1)),
+            timeout: None,
+            cancellation_token: None,
+            drop_detection: None,
+            max_polls: None,
+        }
+    }
+}
+
+/// Typed errors returned by bundle confirmation, retrievable with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub enum BundleConfirmError {
+    /// The confirmation did not complete within `ConfirmBundleConfig::timeout`.
+    Timeout(Duration),
+    /// The bundle did not land within `ConfirmBundleConfig::max_polls` polls.
+    MaxPolls(u32),
+    /// The bundle was accepted but never seen as pending or landed, see `DropDetection`.
+    Dropped { invalid_polls: u32 },
+    /// The block height went past the last valid block height of the bundle.
+    Expired { last_valid_block_height: u64 },
+    /// Jito reported the bundle as failed.
+    Failed { bundle_id: String },
+}
+
+impl fmt::Display for BundleConfirmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleConfirmError::Timeout(timeout) => {
+                write!(f, "bundle confirmation timed out after {:?}", timeout)
+            }
+            BundleConfirmError::MaxPolls(max_polls) => {
+                write!(f, "bundle not confirmed after {} polls", max_polls)
+            }
+            BundleConfirmError::Dropped { invalid_polls } => {
+                write!(f, "bundle dropped after {} invalid polls", invalid_polls)
+            }
+            BundleConfirmError::Expired {
+                last_valid_block_height,
+            } => write!(
+                f,
+                "bundle expired after block height {}",
+                last_valid_block_height
+            ),
+            BundleConfirmError::Failed { bundle_id } => {
+                write!(f, "bundle {} failed", bundle_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BundleConfirmError {}
+
+const MAX_BUNDLE_LEN: usize = 5;
+const MAX_BUNDLE_STATUSES_IDS: usize = 5;
+const DEFAULT_TIP_FLOOR_URL: &str = "https://bundles.jito.wtf/api/v1/bundles/tip_floor";
+const DEFAULT_TIP_FLOOR_TTL: Duration = Duration::from_secs(10);
+const DEFAULT_STATUS_RETRY_COUNT: u32 = 2;
+const DEFAULT_STATUS_RETRY_DELAY: Duration = Duration::from_secs(3);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Backoff of `send_bundle_until_landed` after a rate limited send, multiplied by the attempt.
+const RATE_LIMITED_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Percentiles of the recently landed tips, in SOL, as returned by the tip floor API.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TipFloor {
+    #[serde(rename = "landed_tips_25th_percentile")]
+    pub p25: f64,
+    #[serde(rename = "landed_tips_50th_percentile")]
+    pub p50: f64,
+    #[serde(rename = "landed_tips_75th_percentile")]
+    pub p75: f64,
+    #[serde(rename = "landed_tips_95th_percentile")]
+    pub p95: f64,
+    #[serde(rename = "landed_tips_99th_percentile")]
+    pub p99: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TipPercentile {
+    P25,
+    P50,
+    P75,
+    P95,
+    P99,
+}
+
+impl TipFloor {
+    /// Landed tip at `percentile`, in lamports.
+    pub fn lamports(&self, percentile: TipPercentile) -> u64 {
+        let sol = match percentile {
+            TipPercentile::P25 => self.p25,
+            TipPercentile::P50 => self.p50,
+            TipPercentile::P75 => self.p75,
+            TipPercentile::P95 => self.p95,
+            TipPercentile::P99 => self.p99,
+        };
+        sol_to_lamports(sol)
+    }
+}
+
+const JITO_TIP_ACCOUNTS: [Pubkey; 8] = [
+    pubkey!("96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5"),
+    pubkey!("HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe"),
+    pubkey!("Cw8CFyM9FkoMi7K7Crf6HNQqf4uEMzpKw6QNghXLvLkY"),
+    pubkey!("ADaUMid9yfUytqMBgopwjb2DTLSokTSzL1zt6iGPaS49"),
+    pubkey!("DfXygSm4jCyNCybVYYK6DwvWqjKee8pbDmJGcLWNDXjh"),
+    pubkey!("ADuUkR4vqLUMWXxW9gh6D6L8pMSawimctcNZ5pGwDcEt"),
+    pubkey!("DttWaMuVvTiduZRnguLF7jNxTgiMBZ1hyAumKUiL2KRL"),
+    pubkey!("3AVi9Tg9Uo68tJfuvoKvqKNWKkC5wPdSSdeBnizKZ6jT"),
+];
+
+/// Paths of the Jito endpoints, appended to the client url.
+#[derive(Debug, Clone)]
+pub struct JitoEndpoints {
+    pub bundles: String,
+    pub transactions: String,
+}
+
+impl Default for JitoEndpoints {
+    fn default() -> Self {
+        Self {
+            bundles: "/api/v1/bundles".to_string(),
+            transactions: "/api/v1/transactions".to_string(),
+        }
+    }
+}
+
+/// How the tip account of `get_jito_tip_instruction` is chosen.
+#[derive(Debug, Clone, Default)]
+pub enum TipSelectionStrategy {
+    /// Uniformly at random.
+    #[default]
+    Random,
+    /// Cycling through the tip accounts in order, to spread tips evenly.
+    RoundRobin,
+    /// At random, weighted toward the tip accounts whose bundles landed the fastest during
+    /// the session, as recorded by `JitoClient::record_landing`.
+    Adaptive,
+}
+
+/// Landing statistics of a tip account, used by `TipSelectionStrategy::Adaptive`.
+#[derive(Debug, Clone, Default)]
+struct TipAccountStats {
+    landed: u32,
+    total_elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JitoClientConfig {
+    pub user_agent: Option<String>,
+    /// Additional headers sent with every request, e.g. provider auth headers.
+    pub headers: Vec<(String, String)>,
+    /// Tip accounts used instead of the Jito ones, e.g. after Jito rotates them or for block
+    /// engines sharing the Jito API. Used by all the tip selection strategies, must not be empty.
+    pub tip_accounts: Option<Vec<Pubkey>>,
+    /// Endpoint paths, to be overridden for Jito-compatible relays.
+    pub endpoints: JitoEndpoints,
+    pub tip_selection: TipSelectionStrategy,
+    /// Url of the tip floor API, defaults to the public Jito one.
+    pub tip_floor_url: Option<String>,
+    /// How long a fetched tip floor is reused, defaults to 10 seconds.
+    pub tip_floor_ttl: Option<Duration>,
+    /// Maximum number of attempts of a bundle status request, defaults to 2.
+    pub status_retry_count: Option<u32>,
+    /// Delay between two bundle status request attempts, defaults to 3 seconds.
+    pub status_retry_delay: Option<Duration>,
+    /// Timeout of every HTTP request, defaults to 10 seconds.
+    pub request_timeout: Option<Duration>,
 }
 
 pub struct JitoClient {
     url: String,
+    /// All the block engine urls, starting with `url`.
+    urls: Vec<String>,
     uuid: Option<String>,
     client: Client,
+    tip_accounts: Option<Vec<Pubkey>>,
+    endpoints: JitoEndpoints,
+    tip_selection: TipSelectionStrategy,
+    tip_account_stats: Mutex<HashMap<Pubkey, TipAccountStats>>,
+    tip_account_cursor: AtomicUsize,
+    tip_floor_url: String,
+    tip_floor_ttl: Duration,
+    tip_floor_cache: Mutex<Option<(Instant, TipFloor)>>,
+    status_retry_count: u32,
+    status_retry_delay: Duration,
 }
 
 impl JitoClient {
     pub fn new(url: &String, uuid: Option<String>) -> Result<Self> {
+        Self::new_with_config(url, uuid, JitoClientConfig::default())
+    }
+
+    /// Creates a client for several regional block engines. Single url methods use the first
+    /// url, while `send_bundle_all` fans bundles out to all of them.
+    pub fn new_multi(urls: Vec<String>, uuid: Option<String>) -> Result<Self> {
+        Self::new_multi_with_config(urls, uuid, JitoClientConfig::default())
+    }
+
+    /// Same as `new_multi`, with the same options as `new_with_config`.
+    pub fn new_multi_with_config(
+        urls: Vec<String>,
+        uuid: Option<String>,
+        config: JitoClientConfig,
+    ) -> Result<Self> {
+        let url = urls
+            .first()
+            .ok_or(anyhow!("JitoClient requires at least one url"))?
+            .clone();
+        let mut client = Self::new_with_config(&url, uuid, config)?;
+        client.urls = urls;
+        Ok(client)
+    }
+
+    pub fn new_with_config(
+        url: &String,
+        uuid: Option<String>,
+        config: JitoClientConfig,
+    ) -> Result<Self> {
+        if matches!(&config.tip_accounts, Some(tip_accounts) if tip_accounts.is_empty()) {
+            return Err(anyhow!("new_with_config tip_accounts must not be empty"));
+        }
+
+        let mut builder = Client::builder()
+            .timeout(config.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT))
+            .default_headers(header_map(&config.headers)?);
+        if let Some(user_agent) = config.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
         Ok(Self {
             url: url.clone(),
+            urls: vec![url.clone()],
             uuid,
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(10))
-                .build()?,
+            client: builder.build()?,
+            tip_accounts: config.tip_accounts,
+            endpoints: config.endpoints,
+            tip_selection: config.tip_selection,
+            tip_account_stats: Mutex::new(HashMap::new()),
+            tip_account_cursor: AtomicUsize::new(0),
+            tip_floor_url: config
+                .tip_floor_url
+                .unwrap_or(DEFAULT_TIP_FLOOR_URL.to_string()),
+            tip_floor_ttl: config.tip_floor_ttl.unwrap_or(DEFAULT_TIP_FLOOR_TTL),
+            tip_floor_cache: Mutex::new(None),
+            status_retry_count: config
+                .status_retry_count
+                .unwrap_or(DEFAULT_STATUS_RETRY_COUNT),
+            status_retry_delay: config
+                .status_retry_delay
+                .unwrap_or(DEFAULT_STATUS_RETRY_DELAY),
         })
     }
 
     pub async fn send_bundle(&self, bundle: &Vec<VersionedTransaction>) -> Result<String> {
-        let bundle_base_58: Vec<Result<String>> =
-            bundle.into_iter().map(|tx| tx_to_base58(tx)).collect();
-        let bundle_base_58: Result<Vec<String>> = bundle_base_58.into_iter().collect();
+        check_duplicate_transactions(bundle)?;
+        let bundle_base_58 = encode_bundle(bundle)?;
+        self.send_bundle_base58(bundle_base_58).await
+    }
+
+    /// Sends a bundle of base58 encoded transactions as is, returning the bundle id.
+    pub async fn send_bundle_base58(&self, bundle: Vec<String>) -> Result<String> {
+        check_bundle_len(&bundle)?;
+        self.send_bundle_base58_to(&self.url, bundle).await
+    }
+
+    /// Sends the same bundle to every configured url concurrently, see `new_multi`, and
+    /// returns the bundle id from the first url that accepted it.
+    /// A failure from one url does not abort the others.
+    pub async fn send_bundle_all(&self, bundle: &Vec<VersionedTransaction>) -> Result<String> {
+        check_duplicate_transactions(bundle)?;
+        let bundle_base_58 = encode_bundle(bundle)?;
+        check_bundle_len(&bundle_base_58)?;
+
+        let requests: Vec<_> = self
+            .urls
+            .iter()
+            .map(|url| self.send_bundle_base58_to(url, bundle_base_58.clone()))
+            .collect();
+        let responses = futures::future::join_all(requests).await;
+
+        let mut errors = vec![];
+        let mut bundle_id = None;
+        for (url, response) in self.urls.iter().zip(responses) {
+            match response {
+                Ok(id) => {
+                    bundle_id.get_or_insert(id);
+                }
+                Err(err) => {
+                    warn!("send_bundle_all {}: {}", url, err);
+                    errors.push(format!("{}: {}", url, err));
+                }
+            }
+        }
+
+        bundle_id.ok_or(anyhow!(
+            "send_bundle_all all urls failed: {}",
+            errors.join(", ")
+        ))
+    }
 
-        let data = JitoRequest::new("sendBundle".into(), bundle_base_58?);
-        let mut url = format!("{}/api/v1/bundles", self.url);
+    async fn send_bundle_base58_to(&self, base_url: &str, bundle: Vec<String>) -> Result<String> {
+        let data = JitoRequest::new("sendBundle".into(), bundle);
+        let mut url = format!("{}{}", base_url, self.endpoints.bundles);
         if let Some(uuid) = self.uuid.clone() {
             url = format!("{}?uuid={}", url, uuid);
         }
@@ -133,23 +625,20 @@ impl JitoClient {
             .json(&data)
             .send()
             .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(JitoError::RateLimited(response.text().await.unwrap_or_default()).into());
+        }
         let response_string = response.text().await?;
-        let swap_response: JitoResponse<String> =
-            serde_json::from_str(&response_string).map_err(|err| {
-                anyhow!("send_bundle_base_58 error parsing response {response_string}: {err}")
-            })?;
-        match swap_response.error {
-            Some(err) => return Err(anyhow!("send_bundle_base_58 {:?}", err)),
-            None => {
-                return Ok(swap_response.result.unwrap());
-            }
-        };
+        parse_send_bundle_response(&response_string)
     }
 
     pub async fn send_transaction(&self, tx: &VersionedTransaction) -> Result<String> {
         let encoded_tx_base58 = tx_to_base58(tx)?;
         let data = JitoRequestTx::new("sendTransaction".into(), encoded_tx_base58);
-        let mut url = format!("{}/api/v1/transactions?bundleOnly=true", self.url);
+        let mut url = format!(
+            "{}{}?bundleOnly=true",
+            self.url, self.endpoints.transactions
+        );
         if let Some(uuid) = self.uuid.clone() {
             url = format!("{}&uuid={}", url, uuid);
         }
@@ -161,38 +650,182 @@ impl JitoClient {
             .json(&data)
             .send()
             .await?;
-        let headers = response.headers().clone();
-        let bundle_id = headers
-            .get("x-bundle-id")
-            .ok_or(anyhow!("x-bundle-id header not found"))?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(JitoError::RateLimited(response.text().await.unwrap_or_default()).into());
+        }
+        let bundle_id_header = response.headers().get("x-bundle-id").cloned();
         let response_string = response.text().await?;
-        let swap_response: JitoResponse<String> =
-            serde_json::from_str(&response_string).map_err(|err| {
-                anyhow!("send_transaction error parsing response {response_string}: {err}")
-            })?;
-        match swap_response.error {
-            Some(err) => return Err(anyhow!("send_transaction {:?}", err)),
-            None => {
-                return Ok(bundle_id.to_str().unwrap_or_default().to_string());
+        parse_send_transaction_response(&response_string, bundle_id_header.as_ref())
+    }
+
+    /// Sends a bundle and waits for it to land according to `config.strategy`, reporting how
+    /// long landing took.
+    /// `tip_lamports` is the tip included in the bundle and is only used for reporting.
+    pub async fn send_and_confirm_bundle(
+        &self,
+        rpc_client: &RpcClient,
+        bundle: &Vec<VersionedTransaction>,
+        last_valid_block_height: u64,
+        tip_lamports: u64,
+        config: ConfirmBundleConfig,
+    ) -> Result<BundleLandingReport> {
+        let submitted = self.submit_bundle(rpc_client, bundle).await?;
+        self.confirm_submitted_bundle(
+            rpc_client,
+            bundle,
+            submitted,
+            last_valid_block_height,
+            tip_lamports,
+            config,
+        )
+        .await
+    }
+
+    /// Sends a bundle, recording the slot and the time it was submitted at.
+    async fn submit_bundle(
+        &self,
+        rpc_client: &RpcClient,
+        bundle: &Vec<VersionedTransaction>,
+    ) -> Result<SubmittedBundle> {
+        let submit_slot = rpc_client
+            .get_slot()
+            .await
+            .map_err(|err| anyhow!("get_slot: {}", err))?;
+        let start = Instant::now();
+        let bundle_id = self.send_bundle(bundle).await?;
+
+        Ok(SubmittedBundle {
+            bundle_id,
+            submit_slot,
+            start,
+        })
+    }
+
+    /// Second half of `send_and_confirm_bundle`, once the bundle was accepted.
+    async fn confirm_submitted_bundle(
+        &self,
+        rpc_client: &RpcClient,
+        bundle: &Vec<VersionedTransaction>,
+        submitted: SubmittedBundle,
+        last_valid_block_height: u64,
+        tip_lamports: u64,
+        config: ConfirmBundleConfig,
+    ) -> Result<BundleLandingReport> {
+        let SubmittedBundle {
+            bundle_id,
+            submit_slot,
+            start,
+        } = submitted;
+        let landed_slot = match config.strategy {
+            ConfirmationStrategy::Inflight => {
+                self.wait_for_landed_slot(
+                    rpc_client,
+                    &bundle_id,
+                    last_valid_block_height,
+                    None,
+                    start,
+                    &config,
+                )
+                .await?
+            }
+            ConfirmationStrategy::InflightThenFinalized => {
+                let landed_slot = self
+                    .wait_for_landed_slot(
+                        rpc_client,
+                        &bundle_id,
+                        last_valid_block_height,
+                        None,
+                        start,
+                        &config,
+                    )
+                    .await?;
+                let finalized_slot = self.wait_for_finalized(&bundle_id, start, &config).await?;
+                landed_slot.or(Some(finalized_slot))
+            }
+            ConfirmationStrategy::Signatures => {
+                wait_for_signatures(rpc_client, bundle, last_valid_block_height, start, &config)
+                    .await?
             }
         };
+
+        let report = BundleLandingReport {
+            bundle_id,
+            submit_slot,
+            landed_slot,
+            elapsed: start.elapsed(),
+            tip_lamports,
+            tip_account: self.find_tip_account(bundle),
+        };
+        self.record_landing(&report);
+        info!(
+            bundle_id = %report.bundle_id,
+            submit_slot = report.submit_slot,
+            landed_slot = ?report.landed_slot,
+            elapsed_ms = report.elapsed.as_millis() as u64,
+            tip_lamports = report.tip_lamports,
+            "bundle landing report"
+        );
+        Ok(report)
     }
 
+    /// Waits for a bundle to land, returning the slot it landed in. When a
+    /// `block_height_tracker` is given, the bundle expiry is checked against its cached height
+    /// instead of polling `getBlockHeight`.
+    /// Expiry and failure are returned as `BundleConfirmError::Expired` and
+    /// `BundleConfirmError::Failed`.
     pub async fn confirm_bundle_id(
         &self,
         rpc_client: &RpcClient,
         bundle_id: &String,
         last_valid_block_height: u64,
-        poll_period: Duration,
-    ) -> Result<()> {
+        block_height_tracker: Option<&BlockHeightTracker>,
+        config: ConfirmBundleConfig,
+    ) -> Result<u64> {
+        let landed_slot = self
+            .wait_for_landed_slot(
+                rpc_client,
+                bundle_id,
+                last_valid_block_height,
+                block_height_tracker,
+                Instant::now(),
+                &config,
+            )
+            .await?;
+        if let Some(landed_slot) = landed_slot {
+            return Ok(landed_slot);
+        }
+
+        // The inflight status did not include the slot, look it up in the final status.
+        self.get_bundle_statuses(&[bundle_id.clone()])
+            .await?
+            .first()
+            .map(|bundle_status| bundle_status.slot)
+            .ok_or(anyhow!(
+                "confirm_bundle_id landed slot not found for bundle {}",
+                bundle_id
+            ))
+    }
+
+    /// Polls the inflight bundle status until the bundle lands, returning the landed slot
+    /// when Jito reports it.
+    async fn wait_for_landed_slot(
+        &self,
+        rpc_client: &RpcClient,
+        bundle_id: &String,
+        last_valid_block_height: u64,
+        block_height_tracker: Option<&BlockHeightTracker>,
+        start: Instant,
+        config: &ConfirmBundleConfig,
+    ) -> Result<Option<u64>> {
+        let mut poll = 0;
+        let mut invalid_polls = 0;
+        let mut first_block_height = None;
         loop {
-            let current_block_height = rpc_client
-                .get_block_height()
-                .await
-                .map_err(|err| anyhow!("get_block_height: {}", err))?;
-            if current_block_height > last_valid_block_height {
-                return Err(anyhow!("bundle expired"));
-            }
+            check_deadline(start, poll, config)?;
+            let block_height =
+                check_block_height(rpc_client, last_valid_block_height, block_height_tracker)
+                    .await?;
+            let first_block_height = *first_block_height.get_or_insert(block_height);
 
             match self.get_bundle_status_with_retry(bundle_id).await {
                 Ok(response_string) => {
@@ -203,30 +836,53 @@ impl JitoClient {
                         Ok(status_response) => {
                             if let Some(err) = status_response.error {
                                 error!("status_response: {:?}", err);
-                                break;
+                                return Err(JitoError::from(err).into());
                             };
 
-                            let status = status_response
+                            let Some(bundle_status) = status_response
                                 .result
-                                .unwrap()
-                                .value
-                                .get(0)
-                                .unwrap()
-                                .status
-                                .clone();
+                                .as_ref()
+                                .and_then(|result| result.value.get(0))
+                            else {
+                                info!("Bundle {} not found", bundle_id);
+                                invalid_polls += 1;
+                                check_dropped(
+                                    invalid_polls,
+                                    block_height.saturating_sub(first_block_height),
+                                    config,
+                                )?;
+                                sleep_or_cancelled(
+                                    config.poll_schedule.delay(poll),
+                                    &config.cancellation_token,
+                                )
+                                .await?;
+                                poll += 1;
+                                continue;
+                            };
+                            let status = &bundle_status.status;
                             match status.as_str() {
                                 "Invalid" => {
                                     info!("Bundle {} Invalid", bundle_id);
+                                    invalid_polls += 1;
+                                    check_dropped(
+                                        invalid_polls,
+                                        block_height.saturating_sub(first_block_height),
+                                        config,
+                                    )?;
                                 }
                                 "Pending" => {
                                     info!("Bundle {} Pending", bundle_id);
+                                    invalid_polls = 0;
                                 }
                                 "Failed" => {
-                                    anyhow::bail!("Bundle {} Failed", bundle_id)
+                                    return Err(BundleConfirmError::Failed {
+                                        bundle_id: bundle_id.clone(),
+                                    }
+                                    .into());
                                 }
                                 "Landed" => {
                                     info!("Bundle {} Landed", bundle_id);
-                                    break;
+                                    return Ok(bundle_status.landed_slot);
                                 }
                                 _ => {
                                     anyhow::bail!(
@@ -247,17 +903,162 @@ impl JitoClient {
                 }
             }
 
-            tokio::time::sleep(poll_period).await;
+            sleep_or_cancelled(config.poll_schedule.delay(poll), &config.cancellation_token)
+                .await?;
+            poll += 1;
         }
+    }
 
-        Ok(())
+    /// Polls `getBundleStatuses` until the bundle is finalized, returning its slot.
+    async fn wait_for_finalized(
+        &self,
+        bundle_id: &String,
+        start: Instant,
+        config: &ConfirmBundleConfig,
+    ) -> Result<u64> {
+        let mut poll = 0;
+        loop {
+            check_deadline(start, poll, config)?;
+
+            let bundle_statuses = self.get_bundle_statuses(&[bundle_id.clone()]).await?;
+            if let Some(bundle_status) = bundle_statuses.first() {
+                if bundle_status.confirmation_status == BundleConfirmationStatus::Finalized {
+                    info!("Bundle {} Finalized", bundle_id);
+                    return Ok(bundle_status.slot);
+                }
+                info!(
+                    "Bundle {} {:?}",
+                    bundle_id, bundle_status.confirmation_status
+                );
+            }
+
+            sleep_or_cancelled(config.poll_schedule.delay(poll), &config.cancellation_token)
+                .await?;
+            poll += 1;
+        }
+    }
+
+    /// Returns the status of landed bundles, including bundles older than the few minutes
+    /// covered by `getInflightBundleStatuses`. Bundles that are not found are omitted.
+    pub async fn get_bundle_statuses(&self, bundle_ids: &[String]) -> Result<Vec<BundleStatus>> {
+        let mut url = format!("{}{}", self.url, self.endpoints.bundles);
+        if let Some(uuid) = self.uuid.clone() {
+            url = format!("{}?uuid={}", url, uuid);
+        }
+
+        let mut bundle_statuses = vec![];
+        for chunk in bundle_ids.chunks(MAX_BUNDLE_STATUSES_IDS) {
+            let request = JitoRequest::new("getBundleStatuses".into(), chunk.to_vec());
+            let response = self
+                .client
+                .post(&url)
+                .header(CONTENT_TYPE, "application/json")
+                .header(ACCEPT, "application/json")
+                .json(&request)
+                .send()
+                .await?;
+            let response_string = response.text().await?;
+            let status_response: JitoResponse<
+                JitoResponseContextValue<Option<GetBundleStatusesResponse>>,
+            > = serde_json::from_str(&response_string).map_err(|err| {
+                anyhow!("get_bundle_statuses error parsing response {response_string}: {err}")
+            })?;
+            if let Some(err) = status_response.error {
+                return Err(anyhow!("get_bundle_statuses: {:?}", err));
+            }
+
+            if let Some(result) = status_response.result {
+                for bundle_status in result.value.into_iter().flatten() {
+                    bundle_statuses.push(BundleStatus::try_from(bundle_status)?);
+                }
+            }
+        }
+
+        Ok(bundle_statuses)
+    }
+
+    /// Streams the status transitions of a bundle, e.g. Pending then Landed. Jito does not
+    /// offer a bundle status stream, so the inflight status is polled following
+    /// `config.poll_schedule`. The receiver closes after a terminal status, or when the
+    /// config timeout, poll limit or cancellation is reached.
+    pub fn subscribe_bundle_status(
+        self: Arc<Self>,
+        bundle_id: String,
+        config: ConfirmBundleConfig,
+    ) -> mpsc::Receiver<InflightBundleStatus> {
+        let (tx, rx) = mpsc::channel::<InflightBundleStatus>(16);
+
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let mut poll = 0;
+            let mut last_status = None;
+            loop {
+                if let Err(err) = check_deadline(start, poll, &config) {
+                    info!("subscribe_bundle_status {}: {}", bundle_id, err);
+                    break;
+                }
+
+                match self.get_inflight_bundle_status(&bundle_id).await {
+                    Ok(status) => {
+                        if last_status.as_ref() != Some(&status) {
+                            let is_terminal = status.is_terminal();
+                            last_status = Some(status.clone());
+                            if tx.send(status).await.is_err() || is_terminal {
+                                break;
+                            }
+                        }
+                    }
+                    Err(err) => error!("subscribe_bundle_status {}: {}", bundle_id, err),
+                }
+
+                if sleep_or_cancelled(config.poll_schedule.delay(poll), &config.cancellation_token)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                poll += 1;
+            }
+        });
+
+        rx
+    }
+
+    async fn get_inflight_bundle_status(&self, bundle_id: &String) -> Result<InflightBundleStatus> {
+        let response_string = self.get_bundle_status_with_retry(bundle_id).await?;
+        let status_response: JitoResponse<
+            JitoResponseContextValue<GetInflightBundleStatusesResponse>,
+        > = serde_json::from_str(&response_string).map_err(|err| {
+            anyhow!("get_inflight_bundle_status error parsing response {response_string}: {err}")
+        })?;
+        if let Some(err) = status_response.error {
+            return Err(JitoError::from(err).into());
+        }
+
+        let Some(bundle_status) = status_response
+            .result
+            .and_then(|result| result.value.into_iter().next())
+        else {
+            return Ok(InflightBundleStatus::Invalid);
+        };
+        match bundle_status.status.as_str() {
+            "Invalid" => Ok(InflightBundleStatus::Invalid),
+            "Pending" => Ok(InflightBundleStatus::Pending),
+            "Failed" => Ok(InflightBundleStatus::Failed),
+            "Landed" => Ok(InflightBundleStatus::Landed {
+                slot: bundle_status.landed_slot,
+            }),
+            status => Err(anyhow!(
+                "Unrecognized bundle status {status} for bundle id {bundle_id}"
+            )),
+        }
     }
 
     async fn get_bundle_status_with_retry(&self, bundle_id: &String) -> Result<String> {
         let request = JitoRequest::new("getInflightBundleStatuses".into(), vec![bundle_id.clone()]);
-        let mut url = format!("{}/api/v1/bundles", self.url);
+        let mut url = format!("{}{}", self.url, self.endpoints.bundles);
         if let Some(uuid) = self.uuid.clone() {
-            url = format!("{}&uuid={}", url, uuid);
+            url = format!("{}?uuid={}", url, uuid);
         }
 
         let mut retry_count = 1;
@@ -284,39 +1085,604 @@ impl JitoClient {
                 }
             }
 
-            retry_count += 1;
-            if retry_count == 3 {
+            if retry_count >= self.status_retry_count {
                 anyhow::bail!("getInflightBundleStatuses: max retry reached");
             }
-            tokio::time::sleep(Duration::from_secs(3)).await;
+            retry_count += 1;
+            tokio::time::sleep(self.status_retry_delay).await;
+        }
+    }
+
+    /// Fetches the percentiles of the recently landed tips. The result is cached for the
+    /// configured TTL so that bursts of sends do not hammer the endpoint.
+    pub async fn get_tip_floor(&self) -> Result<TipFloor> {
+        if let Some((fetched_at, tip_floor)) = self.tip_floor_cache.lock().unwrap().as_ref() {
+            if fetched_at.elapsed() < self.tip_floor_ttl {
+                return Ok(tip_floor.clone());
+            }
         }
+
+        let response = self
+            .client
+            .get(&self.tip_floor_url)
+            .header(ACCEPT, "application/json")
+            .send()
+            .await?;
+        let response_string = response.text().await?;
+        let tip_floors: Vec<TipFloor> = serde_json::from_str(&response_string).map_err(|err| {
+            anyhow!("get_tip_floor error parsing response {response_string}: {err}")
+        })?;
+        let tip_floor = tip_floors
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("get_tip_floor empty response"))?;
+
+        *self.tip_floor_cache.lock().unwrap() = Some((Instant::now(), tip_floor.clone()));
+        Ok(tip_floor)
     }
 
-    pub fn get_jito_tip_instruction(payer: &Pubkey, lamports: u64) -> Instruction {
-        let tip_account = get_random_tip_account();
+    /// Same as `get_jito_tip_instruction`, tipping the landed tip at `percentile`.
+    /// Falls back to `default_lamports` if the tip floor cannot be fetched.
+    pub async fn get_jito_tip_instruction_dynamic(
+        &self,
+        payer: &Pubkey,
+        percentile: TipPercentile,
+        default_lamports: u64,
+    ) -> Instruction {
+        let lamports = match self.get_tip_floor().await {
+            Ok(tip_floor) => tip_floor.lamports(percentile),
+            Err(err) => {
+                warn!(
+                    "get_tip_floor: {}, using default tip {}",
+                    err, default_lamports
+                );
+                default_lamports
+            }
+        };
+        self.get_jito_tip_instruction(payer, lamports)
+    }
+
+    pub fn get_jito_tip_instruction(&self, payer: &Pubkey, lamports: u64) -> Instruction {
+        let tip_account = match self.tip_selection {
+            TipSelectionStrategy::Random => get_random_tip_account(self.tip_accounts()),
+            TipSelectionStrategy::RoundRobin => self.next_tip_account(),
+            TipSelectionStrategy::Adaptive => self.get_adaptive_tip_account(),
+        };
         transfer(payer, &tip_account, lamports)
     }
+
+    /// Returns the tip accounts one after the other, wrapping around at the end of the list.
+    pub fn next_tip_account(&self) -> Pubkey {
+        let tip_accounts = self.tip_accounts();
+        let cursor = self.tip_account_cursor.fetch_add(1, Ordering::Relaxed);
+        tip_accounts[cursor % tip_accounts.len()]
+    }
+
+    /// Records the landing time of a bundle against its tip account, to be used by
+    /// `TipSelectionStrategy::Adaptive`. Called by `send_and_confirm_bundle` for every
+    /// landed bundle.
+    pub fn record_landing(&self, report: &BundleLandingReport) {
+        let Some(tip_account) = report.tip_account else {
+            return;
+        };
+        let mut tip_account_stats = self.tip_account_stats.lock().unwrap();
+        let stats = tip_account_stats.entry(tip_account).or_default();
+        stats.landed += 1;
+        stats.total_elapsed += report.elapsed;
+    }
+
+    /// Picks a tip account with a probability inversely proportional to its average landing
+    /// time. Accounts without landings get the best known weight so they keep being explored.
+    fn get_adaptive_tip_account(&self) -> Pubkey {
+        let tip_accounts = self.tip_accounts();
+        let tip_account_stats = self.tip_account_stats.lock().unwrap();
+        let weights: Vec<Option<f64>> = tip_accounts
+            .iter()
+            .map(|tip_account| {
+                tip_account_stats.get(tip_account).map(|stats| {
+                    let average_elapsed = stats.total_elapsed.as_secs_f64() / stats.landed as f64;
+                    1.0 / average_elapsed.max(0.001)
+                })
+            })
+            .collect();
+        drop(tip_account_stats);
+
+        let best_weight = weights.iter().flatten().cloned().fold(0.0, f64::max);
+        if best_weight <= 0.0 {
+            return get_random_tip_account(tip_accounts);
+        }
+        let weights = weights
+            .into_iter()
+            .map(|weight| weight.unwrap_or(best_weight));
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => tip_accounts[distribution.sample(&mut rand::thread_rng())],
+            Err(_) => get_random_tip_account(tip_accounts),
+        }
+    }
+
+    /// Returns the first tip account referenced by the bundle transactions.
+    fn find_tip_account(&self, bundle: &[VersionedTransaction]) -> Option<Pubkey> {
+        let tip_accounts = self.tip_accounts();
+        bundle.iter().find_map(|tx| {
+            tx.message
+                .static_account_keys()
+                .iter()
+                .find(|key| tip_accounts.contains(key))
+                .copied()
+        })
+    }
+
+    /// Tip accounts configured on the client, defaulting to the Jito ones.
+    pub fn tip_accounts(&self) -> &[Pubkey] {
+        match &self.tip_accounts {
+            Some(tip_accounts) => tip_accounts,
+            None => &JITO_TIP_ACCOUNTS,
+        }
+    }
+}
+
+/// Encodes a bundle in base58, the format expected by `sendBundle`.
+pub fn encode_bundle(bundle: &Vec<VersionedTransaction>) -> Result<Vec<String>> {
+    bundle.iter().map(tx_to_base58).collect()
+}
+
+fn check_bundle_len(bundle: &[String]) -> Result<()> {
+    if bundle.is_empty() || bundle.len() > MAX_BUNDLE_LEN {
+        return Err(anyhow!(
+            "send_bundle_base_58 bundle must contain 1 to {} transactions, got {}",
+            MAX_BUNDLE_LEN,
+            bundle.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Errors if two transactions of the bundle share the same signature, which Jito rejects.
+fn check_duplicate_transactions(bundle: &[VersionedTransaction]) -> Result<()> {
+    let mut indices: HashMap<&Signature, usize> = HashMap::new();
+    for (j, tx) in bundle.iter().enumerate() {
+        if let Some(signature) = tx.signatures.first() {
+            if let Some(i) = indices.insert(signature, j) {
+                return Err(anyhow!("duplicate transaction at indices {i} and {j}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a base58 encoded bundle, e.g. captured from the Jito explorer, to inspect or
+/// simulate it locally.
+pub fn bundle_from_base58(encoded: &[String]) -> Result<Vec<VersionedTransaction>> {
+    encoded.iter().map(tx_from_base58).collect()
+}
+
+/// Tip bid over resubmission attempts: attempt `n` (starting from 0) tips
+/// `min(start * factor^n, max)` lamports.
+#[derive(Debug, Clone)]
+pub struct TipEscalation {
+    pub start: u64,
+    pub factor: f64,
+    pub max: u64,
+}
+
+impl TipEscalation {
+    /// Same tip on every attempt.
+    pub fn fixed(lamports: u64) -> Self {
+        Self {
+            start: lamports,
+            factor: 1.0,
+            max: lamports,
+        }
+    }
+
+    pub fn tip_for_attempt(&self, attempt: u32) -> u64 {
+        let tip = self.start as f64 * self.factor.powi(attempt as i32);
+        tip.min(self.max as f64) as u64
+    }
+}
+
+/// Bundle accepted by the block engine, see `JitoClient::submit_bundle`.
+struct SubmittedBundle {
+    bundle_id: String,
+    submit_slot: u64,
+    start: Instant,
+}
+
+/// Sends a bundle until it lands, rebuilding it with a fresh blockhash and the tip in lamports
+/// for the attempt, as given by `escalation`. `build_fn` must return the re-signed bundle.
+/// A new attempt is only made once the previous bundle can no longer land, i.e. it expired or
+/// Jito reported it as failed, or when its send was rate limited, after backing off. Any other
+/// error is returned, as the bundle may still land.
+/// Gives up after `max_attempts` or once `deadline` has passed, which also bounds the
+/// confirmation timeout of each attempt.
+pub async fn send_bundle_until_landed<F>(
+    rpc_client: &RpcClient,
+    jito_client: &JitoClient,
+    build_fn: F,
+    escalation: TipEscalation,
+    max_attempts: usize,
+    confirm_config: ConfirmBundleConfig,
+    deadline: Option<Instant>,
+) -> Result<BundleLandingReport>
+where
+    F: Fn(Hash, u64) -> Result<Vec<VersionedTransaction>>,
+{
+    for attempt in 1..=max_attempts {
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                anyhow::bail!(
+                    "send_bundle_until_landed deadline reached after {} attempts",
+                    attempt - 1
+                );
+            }
+        }
+
+        check_cancelled(&confirm_config.cancellation_token)?;
+        let (blockhash, last_valid_block_height) = rpc_client
+            .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed())
+            .await
+            .map_err(|err| anyhow!("get_latest_blockhash_with_commitment: {}", err))?;
+        let tip_lamports = escalation.tip_for_attempt(attempt as u32 - 1);
+        let bundle = build_fn(blockhash, tip_lamports)?;
+
+        check_cancelled(&confirm_config.cancellation_token)?;
+        let submitted = match jito_client.submit_bundle(rpc_client, &bundle).await {
+            Ok(submitted) => submitted,
+            Err(err)
+                if matches!(
+                    err.downcast_ref::<JitoError>(),
+                    Some(JitoError::RateLimited(_))
+                ) =>
+            {
+                warn!(
+                    "send_bundle_until_landed attempt {}/{}: {}",
+                    attempt, max_attempts, err
+                );
+                let mut backoff = RATE_LIMITED_BACKOFF * attempt as u32;
+                if let Some(deadline) = deadline {
+                    backoff = backoff.min(deadline.saturating_duration_since(Instant::now()));
+                }
+                sleep_or_cancelled(backoff, &confirm_config.cancellation_token).await?;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut attempt_config = confirm_config.clone();
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            attempt_config.timeout = Some(
+                attempt_config
+                    .timeout
+                    .map_or(remaining, |timeout| timeout.min(remaining)),
+            );
+        }
+        match jito_client
+            .confirm_submitted_bundle(
+                rpc_client,
+                &bundle,
+                submitted,
+                last_valid_block_height,
+                tip_lamports,
+                attempt_config,
+            )
+            .await
+        {
+            Ok(report) => return Ok(report),
+            Err(err)
+                if matches!(
+                    err.downcast_ref::<BundleConfirmError>(),
+                    Some(BundleConfirmError::Expired { .. } | BundleConfirmError::Failed { .. })
+                ) =>
+            {
+                warn!(
+                    "send_bundle_until_landed attempt {}/{}: {}",
+                    attempt, max_attempts, err
+                )
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(anyhow!(
+        "send_bundle_until_landed bundle not landed after {} attempts",
+        max_attempts
+    ))
+}
+
+/// Polls the signature statuses of the bundle transactions until all of them are confirmed,
+/// returning the highest slot they landed in.
+async fn wait_for_signatures(
+    rpc_client: &RpcClient,
+    bundle: &Vec<VersionedTransaction>,
+    last_valid_block_height: u64,
+    start: Instant,
+    config: &ConfirmBundleConfig,
+) -> Result<Option<u64>> {
+    let signatures = bundle
+        .iter()
+        .map(|tx| {
+            tx.signatures
+                .first()
+                .copied()
+                .ok_or(anyhow!("wait_for_signatures transaction without signature"))
+        })
+        .collect::<Result<Vec<Signature>>>()?;
+
+    let mut poll = 0;
+    loop {
+        check_deadline(start, poll, config)?;
+        check_block_height(rpc_client, last_valid_block_height, None).await?;
+
+        let statuses = rpc_client
+            .get_signature_statuses(&signatures)
+            .await
+            .map_err(|err| anyhow!("get_signature_statuses: {}", err))?
+            .value;
+
+        let mut landed_slot = 0;
+        let mut all_confirmed = true;
+        for (signature, status) in signatures.iter().zip(statuses) {
+            match status {
+                Some(status) => {
+                    if let Some(err) = status.err {
+                        anyhow::bail!("Transaction {} failed: {}", signature, err);
+                    }
+                    if !status.satisfies_commitment(CommitmentConfig::confirmed()) {
+                        all_confirmed = false;
+                    }
+                    landed_slot = landed_slot.max(status.slot);
+                }
+                None => all_confirmed = false,
+            }
+        }
+        if all_confirmed {
+            return Ok(Some(landed_slot));
+        }
+
+        sleep_or_cancelled(config.poll_schedule.delay(poll), &config.cancellation_token).await?;
+        poll += 1;
+    }
+}
+
+/// Returns an error if the confirmation was cancelled or timed out.
+fn check_deadline(start: Instant, poll: u32, config: &ConfirmBundleConfig) -> Result<()> {
+    check_cancelled(&config.cancellation_token)?;
+    if let Some(timeout) = config.timeout {
+        if start.elapsed() >= timeout {
+            return Err(BundleConfirmError::Timeout(timeout).into());
+        }
+    }
+    if let Some(max_polls) = config.max_polls {
+        if poll >= max_polls {
+            return Err(BundleConfirmError::MaxPolls(max_polls).into());
+        }
+    }
+    Ok(())
 }
 
-fn get_random_tip_account() -> Pubkey {
-    let tip_accounts = vec![
-        pubkey!("96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5"),
-        pubkey!("HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe"),
-        pubkey!("Cw8CFyM9FkoMi7K7Crf6HNQqf4uEMzpKw6QNghXLvLkY"),
-        pubkey!("ADaUMid9yfUytqMBgopwjb2DTLSokTSzL1zt6iGPaS49"),
-        pubkey!("DfXygSm4jCyNCybVYYK6DwvWqjKee8pbDmJGcLWNDXjh"),
-        pubkey!("ADuUkR4vqLUMWXxW9gh6D6L8pMSawimctcNZ5pGwDcEt"),
-        pubkey!("DttWaMuVvTiduZRnguLF7jNxTgiMBZ1hyAumKUiL2KRL"),
-        pubkey!("3AVi9Tg9Uo68tJfuvoKvqKNWKkC5wPdSSdeBnizKZ6jT"),
-    ];
-    let idx = random_in_range(0, tip_accounts.len() as i32 - 1);
-    tip_accounts.get(idx as usize).unwrap().clone()
+/// Returns a `Dropped` error if the bundle has been invalid or unknown for too many polls
+/// while the chain kept progressing, according to `config.drop_detection`.
+fn check_dropped(
+    invalid_polls: u32,
+    block_height_advance: u64,
+    config: &ConfirmBundleConfig,
+) -> Result<()> {
+    if let Some(drop_detection) = &config.drop_detection {
+        if invalid_polls > drop_detection.max_invalid_polls
+            && block_height_advance >= drop_detection.min_block_height_advance
+        {
+            return Err(BundleConfirmError::Dropped { invalid_polls }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if the block height went past `last_valid_block_height`, otherwise
+/// the current block height.
+async fn check_block_height(
+    rpc_client: &RpcClient,
+    last_valid_block_height: u64,
+    block_height_tracker: Option<&BlockHeightTracker>,
+) -> Result<u64> {
+    let current_block_height = match block_height_tracker {
+        Some(tracker) => tracker.height(),
+        None => rpc_client
+            .get_block_height()
+            .await
+            .map_err(|err| anyhow!("get_block_height: {}", err))?,
+    };
+    if current_block_height > last_valid_block_height {
+        return Err(BundleConfirmError::Expired {
+            last_valid_block_height,
+        }
+        .into());
+    }
+    Ok(current_block_height)
 }
 
-fn random_in_range(min: i32, max: i32) -> i32 {
-    if min >= max {
-        panic!("min should be lower than max")
+/// Returns the bundle id of a `sendBundle` response.
+fn parse_send_bundle_response(response_string: &str) -> Result<String> {
+    let swap_response: JitoResponse<String> =
+        serde_json::from_str(response_string).map_err(|err| {
+            anyhow!("send_bundle_base_58 error parsing response {response_string}: {err}")
+        })?;
+    match swap_response.error {
+        Some(err) => Err(JitoError::from(err).into()),
+        None => swap_response.result.ok_or(anyhow!(
+            "send_bundle: empty result and no error in response {response_string}"
+        )),
+    }
+}
+
+/// Returns the bundle id of a `sendTransaction` response, read from its `x-bundle-id` header.
+fn parse_send_transaction_response(
+    response_string: &str,
+    bundle_id_header: Option<&HeaderValue>,
+) -> Result<String> {
+    let swap_response: JitoResponse<String> =
+        serde_json::from_str(response_string).map_err(|err| {
+            anyhow!("send_transaction error parsing response {response_string}: {err}")
+        })?;
+    if let Some(err) = swap_response.error {
+        return Err(JitoError::from(err).into());
+    }
+
+    let bundle_id = bundle_id_header.ok_or(anyhow!(
+        "send_transaction: x-bundle-id header not found in response {response_string}"
+    ))?;
+    let bundle_id = bundle_id
+        .to_str()
+        .map_err(|err| anyhow!("send_transaction: invalid x-bundle-id header: {err}"))?;
+    if bundle_id.is_empty() {
+        return Err(anyhow!("send_transaction: empty x-bundle-id header"));
+    }
+    Ok(bundle_id.to_string())
+}
+
+fn get_random_tip_account(tip_accounts: &[Pubkey]) -> Pubkey {
+    let idx = rand::thread_rng().gen_range(0..tip_accounts.len());
+    tip_accounts[idx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{
+        message::{Message, VersionedMessage},
+        signature::Keypair,
+        signer::Signer,
+    };
+
+    fn signed_transfer(payer: &Keypair, lamports: u64) -> VersionedTransaction {
+        let ix = transfer(&payer.pubkey(), &Pubkey::new_unique(), lamports);
+        let message = VersionedMessage::Legacy(Message::new(&[ix], Some(&payer.pubkey())));
+        VersionedTransaction::try_new(message, &[payer]).unwrap()
+    }
+
+    #[test]
+    fn bundle_from_base58_round_trips_encode_bundle() {
+        let payer = Keypair::new();
+        let bundle = vec![signed_transfer(&payer, 1), signed_transfer(&payer, 2)];
+
+        let encoded = encode_bundle(&bundle).unwrap();
+        assert_eq!(bundle_from_base58(&encoded).unwrap(), bundle);
+    }
+
+    #[test]
+    fn check_duplicate_transactions_accepts_distinct_transactions() {
+        let payer = Keypair::new();
+        let bundle = vec![signed_transfer(&payer, 1), signed_transfer(&payer, 2)];
+        assert!(check_duplicate_transactions(&bundle).is_ok());
+    }
+
+    #[test]
+    fn check_duplicate_transactions_rejects_duplicates() {
+        let payer = Keypair::new();
+        let tx = signed_transfer(&payer, 1);
+        let bundle = vec![tx.clone(), signed_transfer(&payer, 2), tx];
+
+        let err = check_duplicate_transactions(&bundle).unwrap_err();
+        assert_eq!(err.to_string(), "duplicate transaction at indices 0 and 2");
+    }
+
+    fn landing_report(tip_account: Pubkey, elapsed: Duration) -> BundleLandingReport {
+        BundleLandingReport {
+            bundle_id: "bundle-id".to_string(),
+            submit_slot: 0,
+            landed_slot: Some(1),
+            elapsed,
+            tip_lamports: 1_000,
+            tip_account: Some(tip_account),
+        }
+    }
+
+    #[test]
+    fn adaptive_tip_account_favors_faster_landings() {
+        let fast = Pubkey::new_unique();
+        let slow = Pubkey::new_unique();
+        let client = JitoClient::new_with_config(
+            &"https://example.com".to_string(),
+            None,
+            JitoClientConfig {
+                tip_accounts: Some(vec![fast, slow]),
+                tip_selection: TipSelectionStrategy::Adaptive,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let count_fast = |client: &JitoClient| {
+            (0..1_000)
+                .filter(|_| client.get_adaptive_tip_account() == fast)
+                .count()
+        };
+
+        // Without landings both accounts are picked uniformly.
+        let fast_before = count_fast(&client);
+        assert!((350..=650).contains(&fast_before), "{fast_before}");
+
+        for _ in 0..5 {
+            client.record_landing(&landing_report(fast, Duration::from_millis(100)));
+            client.record_landing(&landing_report(slow, Duration::from_secs(10)));
+        }
+
+        let fast_after = count_fast(&client);
+        assert!(fast_after >= 950, "{fast_after}");
+    }
+
+    #[test]
+    fn parse_send_bundle_response_returns_bundle_id() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":"bundle-id"}"#;
+        assert_eq!(parse_send_bundle_response(response).unwrap(), "bundle-id");
+    }
+
+    #[test]
+    fn parse_send_bundle_response_errors_on_null_result_and_error() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":null,"error":null}"#;
+        let err = parse_send_bundle_response(response).unwrap_err();
+        assert!(err.to_string().contains("empty result and no error"));
+    }
+
+    #[test]
+    fn parse_send_bundle_response_returns_jito_error() {
+        let response =
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32097,"message":"rate limited"}}"#;
+        let err = parse_send_bundle_response(response).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JitoError>(),
+            Some(JitoError::RateLimited(_))
+        ));
+    }
+
+    #[test]
+    fn parse_send_transaction_response_reads_bundle_id_header() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":"signature"}"#;
+        let header = HeaderValue::from_static("bundle-id");
+        assert_eq!(
+            parse_send_transaction_response(response, Some(&header)).unwrap(),
+            "bundle-id"
+        );
+    }
+
+    #[test]
+    fn parse_send_transaction_response_errors_on_missing_header() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":"signature"}"#;
+        let err = parse_send_transaction_response(response, None).unwrap_err();
+        assert!(err.to_string().contains("x-bundle-id header not found"));
+    }
+
+    #[test]
+    fn parse_send_transaction_response_errors_on_empty_header() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":"signature"}"#;
+        let header = HeaderValue::from_static("");
+        let err = parse_send_transaction_response(response, Some(&header)).unwrap_err();
+        assert!(err.to_string().contains("empty x-bundle-id header"));
+    }
+
+    #[test]
+    fn parse_send_transaction_response_errors_on_null_result_and_error() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":null,"error":null}"#;
+        let err = parse_send_transaction_response(response, None).unwrap_err();
+        assert!(err.to_string().contains("x-bundle-id header not found"));
     }
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..=max)
 }
@@ -1,10 +1,34 @@
+use crate::http::header_map;
+use crate::parse::parse_pubkey;
+use crate::token::{token_account_rent, MAX_MULTIPLE_ACCOUNTS};
 use anyhow::{anyhow, Result};
+use rand::Rng;
 use reqwest::{
     header::{ACCEPT, CONTENT_TYPE},
-    Client,
+    Client, RequestBuilder, StatusCode,
+};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    address_lookup_table::{state::AddressLookupTable, AddressLookupTableAccount},
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::{v0, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::VersionedTransaction,
+};
+use solana_trading_core::conversions::{base64_to_bytes, tx_from_base64, units_to_ui_number};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
-use solana_sdk::pubkey::Pubkey;
-use std::time::Duration;
+
+const MAX_EXCLUDE_AMM_KEYS_ATTEMPTS: usize = 3;
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+const DEFAULT_TOKEN_API_URL: &str = "https://tokens.jup.ag/token";
 
 #[derive(Debug, Clone)]
 pub enum SwapMode {
@@ -33,6 +57,10 @@ pub struct GetQuoteParams {
     pub exclude_dexes: Option<String>,
     pub restrict_intermediate_tokens: bool,
     pub only_direct_routes: bool,
+    /// Platform fee charged on the output amount, requires a `fee_account` when swapping.
+    pub platform_fee_bps: Option<u64>,
+    /// Maximum number of accounts of the route, to leave room for additional instructions.
+    pub max_accounts: Option<u64>,
 }
 
 impl Default for GetQuoteParams {
@@ -48,6 +76,8 @@ impl Default for GetQuoteParams {
             exclude_dexes: None,
             restrict_intermediate_tokens: false,
             only_direct_routes: false,
+            platform_fee_bps: None,
+            max_accounts: None,
         }
     }
 }
@@ -64,6 +94,41 @@ pub struct GetSwapParams {
     pub dynamic_compute_unit_limit: bool,
     pub skip_user_accounts_rpc_calls: bool,
     pub quote_response: QuoteResponse,
+    /// Token account collecting the platform fee, required when the quote has a platform fee.
+    #[serde(
+        serialize_with = "serialize_optional_pubkey",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fee_account: Option<Pubkey>,
+}
+
+impl GetSwapParams {
+    /// Checks that `fee_account` is set if and only if the quote has a platform fee, so that
+    /// the fee is neither silently left uncollected nor sent to an unset account.
+    fn validate_fee_account(&self) -> Result<()> {
+        match (&self.quote_response.platform_fee, self.fee_account) {
+            (Some(_), None) => Err(anyhow!(
+                "validate_fee_account quote has a platform fee but no fee_account is set"
+            )),
+            (None, Some(_)) => Err(anyhow!(
+                "validate_fee_account fee_account is set but the quote has no platform fee"
+            )),
+            (Some(_), Some(fee_account)) if fee_account == Pubkey::default() => Err(anyhow!(
+                "validate_fee_account fee_account is the default pubkey"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn serialize_optional_pubkey<S: serde::Serializer>(
+    pubkey: &Option<Pubkey>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match pubkey {
+        Some(pubkey) => serializer.serialize_str(&pubkey.to_string()),
+        None => serializer.serialize_none(),
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -76,11 +141,127 @@ pub struct QuoteResponse {
     pub other_amount_threshold: String,
     pub swap_mode: String,
     pub slippage_bps: u64,
-    pub platform_fee: Option<f64>,
+    pub platform_fee: Option<PlatformFee>,
     pub price_impact_pct: String,
     pub route_plan: Vec<RoutePlanStep>,
     pub context_slot: u64,
     pub time_taken: f64,
+    /// Only returned by newer Jupiter versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub most_reliable_amms_quote_report: Option<MostReliableAmmsQuoteReport>,
+    /// Only returned by newer Jupiter versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score_report: Option<serde_json::Value>,
+}
+
+/// Platform fee taken by the swap, returned when `platform_fee_bps` is set on the quote.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformFee {
+    pub amount: String,
+    pub fee_bps: u64,
+}
+
+impl QuoteResponse {
+    /// Parsed `in_amount`, in input token units.
+    pub fn in_amount_u64(&self) -> Result<u64> {
+        parse_amount(&self.in_amount, "in_amount")
+    }
+
+    /// Parsed `out_amount`, in output token units.
+    pub fn out_amount_u64(&self) -> Result<u64> {
+        parse_amount(&self.out_amount, "out_amount")
+    }
+
+    /// Slippage-protected bound of the swap, parsed from `other_amount_threshold`: the minimum
+    /// output amount for `ExactIn` quotes, the maximum input amount for `ExactOut` quotes.
+    pub fn min_out_or_max_in(&self) -> Result<u64> {
+        if self.swap_mode != SwapMode::ExactIn.to_string()
+            && self.swap_mode != SwapMode::ExactOut.to_string()
+        {
+            return Err(anyhow!(
+                "min_out_or_max_in unknown swap mode {}",
+                self.swap_mode
+            ));
+        }
+        parse_amount(&self.other_amount_threshold, "other_amount_threshold")
+    }
+
+    /// Price of the quote in output tokens per input token, in UI units.
+    pub fn effective_price(&self, input_decimals: u8, output_decimals: u8) -> Result<f64> {
+        let in_amount = units_to_ui_number(self.in_amount_u64()?, input_decimals as u32);
+        let out_amount = units_to_ui_number(self.out_amount_u64()?, output_decimals as u32);
+        if in_amount == 0.0 {
+            return Err(anyhow!("quote in_amount is zero"));
+        }
+        Ok(out_amount / in_amount)
+    }
+
+    /// Returns the AMM key and out amount of the best quote among the most reliable AMMs,
+    /// when Jupiter reports them.
+    pub fn most_reliable_route(&self) -> Option<(String, u64)> {
+        self.most_reliable_amms_quote_report
+            .as_ref()?
+            .info
+            .iter()
+            .filter_map(|(amm_key, out_amount)| {
+                out_amount
+                    .parse::<u64>()
+                    .ok()
+                    .map(|out_amount| (amm_key.clone(), out_amount))
+            })
+            .max_by_key(|(_, out_amount)| *out_amount)
+    }
+
+    /// One-line summary of the route for logging, e.g.
+    /// `Raydium 70% -> Orca 30% (EPjF..Dt1v->So11..1112)`.
+    pub fn route_summary(&self) -> String {
+        let steps = self
+            .route_plan
+            .iter()
+            .map(|step| format!("{} {}%", step.swap_info.label, step.percent))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+
+        let mut mints: Vec<&str> = vec![&self.input_mint];
+        for step in &self.route_plan {
+            if mints.last() != Some(&step.swap_info.output_mint.as_str()) {
+                mints.push(&step.swap_info.output_mint);
+            }
+        }
+        let path = mints
+            .iter()
+            .map(|mint| short_address(mint))
+            .collect::<Vec<_>>()
+            .join("->");
+
+        format!("{} ({})", steps, path)
+    }
+
+    /// Distinct AMM labels used by the route, in route order.
+    pub fn route_labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = vec![];
+        for step in &self.route_plan {
+            if !labels.contains(&step.swap_info.label) {
+                labels.push(step.swap_info.label.clone());
+            }
+        }
+        labels
+    }
+}
+
+fn short_address(address: &str) -> String {
+    if address.len() <= 8 {
+        return address.to_string();
+    }
+    format!("{}..{}", &address[..4], &address[address.len() - 4..])
+}
+
+/// Out amounts quoted by the most reliable AMMs, keyed by AMM key.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MostReliableAmmsQuoteReport {
+    pub info: HashMap<String, String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -111,6 +292,15 @@ pub struct SwapResponse {
     pub prioritization_fee_lamports: u64,
 }
 
+impl SwapResponse {
+    /// Decodes the swap transaction. Both legacy transactions (returned when
+    /// `as_legacy_transaction` is set) and v0 transactions decode into a `VersionedTransaction`,
+    /// which can be signed and sent the same way.
+    pub fn transaction(&self) -> Result<VersionedTransaction> {
+        tx_from_base64(&self.swap_transaction)
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct SwapInstructionsResponse {
@@ -141,9 +331,228 @@ pub struct JupiterAccountMeta {
     pub is_writable: bool,
 }
 
+impl JupiterInstruction {
+    /// Decodes the program id, accounts and base64 data into an `Instruction`.
+    pub fn to_instruction(&self) -> Result<Instruction> {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|account| {
+                Ok(AccountMeta {
+                    pubkey: parse_pubkey(&account.pubkey)?,
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Instruction {
+            program_id: parse_pubkey(&self.program_id)?,
+            accounts,
+            data: base64_to_bytes(&self.data)?,
+        })
+    }
+}
+
+impl SwapInstructionsResponse {
+    /// All the instructions in execution order: compute budget, setup, token ledger, swap,
+    /// cleanup, then the other instructions.
+    pub fn instructions(&self) -> Result<Vec<Instruction>> {
+        self.compute_budget_instructions
+            .iter()
+            .chain(self.setup_instructions.iter())
+            .chain(self.token_ledger_instruction.iter())
+            .chain(std::iter::once(&self.swap_instruction))
+            .chain(self.cleanup_instruction.iter().flatten())
+            .chain(self.other_instructions.iter())
+            .map(JupiterInstruction::to_instruction)
+            .collect()
+    }
+}
+
+/// Builds and signs a v0 swap transaction from the instructions returned by
+/// `get_swap_instructions`, using `alts` to compress the account keys.
+pub fn build_swap_transaction(
+    swap_instructions: &SwapInstructionsResponse,
+    payer: &Keypair,
+    blockhash: Hash,
+    alts: &[AddressLookupTableAccount],
+) -> Result<VersionedTransaction> {
+    let instructions = swap_instructions.instructions()?;
+    let message = v0::Message::try_compile(&payer.pubkey(), &instructions, alts, blockhash)
+        .map_err(|err| anyhow!("build_swap_transaction compile message: {}", err))?;
+    VersionedTransaction::try_new(VersionedMessage::V0(message), &[payer])
+        .map_err(|err| anyhow!("build_swap_transaction sign: {}", err))
+}
+
+/// Fetches and decodes the address lookup tables referenced by a swap, e.g. from
+/// `SwapInstructionsResponse::address_lookup_table_addresses`.
+/// Errors if any of the tables does not exist anymore.
+pub async fn resolve_address_lookup_tables(
+    rpc: &RpcClient,
+    addresses: &[String],
+) -> Result<Vec<AddressLookupTableAccount>> {
+    let keys = addresses
+        .iter()
+        .map(|address| parse_pubkey(address))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut alts = Vec::with_capacity(keys.len());
+    for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = rpc
+            .get_multiple_accounts(chunk)
+            .await
+            .map_err(|err| anyhow!("resolve_address_lookup_tables: {}", err))?;
+        for (key, account) in chunk.iter().zip(accounts) {
+            let account = account.ok_or(anyhow!("address lookup table {} not found", key))?;
+            let table = AddressLookupTable::deserialize(&account.data).map_err(|err| {
+                anyhow!(
+                    "could not deserialize address lookup table {}: {}",
+                    key,
+                    err
+                )
+            })?;
+            alts.push(AddressLookupTableAccount {
+                key: *key,
+                addresses: table.addresses.to_vec(),
+            });
+        }
+    }
+
+    Ok(alts)
+}
+
+/// Latency and freshness of a quote, separating Jupiter compute time from network time.
+#[derive(Debug, Clone)]
+pub struct QuoteMeta {
+    pub server_time_taken: f64,
+    pub client_rtt: Duration,
+    pub context_slot: u64,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct PriceResponse {
+    data: HashMap<String, Option<PriceData>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct PriceData {
+    price: serde_json::Value,
+}
+
+/// Overrides of the routing parameters of a `GetQuoteParams`, see `get_best_quote`.
+/// `None` fields keep the base value.
+#[derive(Debug, Clone, Default)]
+pub struct QuoteVariant {
+    pub only_direct_routes: Option<bool>,
+    pub dexes: Option<String>,
+    pub exclude_dexes: Option<String>,
+}
+
+impl QuoteVariant {
+    fn apply(&self, mut params: GetQuoteParams) -> GetQuoteParams {
+        if let Some(only_direct_routes) = self.only_direct_routes {
+            params.only_direct_routes = only_direct_routes;
+        }
+        if let Some(dexes) = &self.dexes {
+            params.dexes = Some(dexes.clone());
+        }
+        if let Some(exclude_dexes) = &self.exclude_dexes {
+            params.exclude_dexes = Some(exclude_dexes.clone());
+        }
+        params
+    }
+}
+
+/// Metadata of a mint.
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub mint: Pubkey,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u8,
+    pub is_token_2022: bool,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TokenApiResponse {
+    symbol: String,
+    name: String,
+    decimals: u8,
+}
+
+/// Paths of the Jupiter endpoints, appended to the client url.
+#[derive(Debug, Clone)]
+pub struct JupiterEndpoints {
+    pub quote: String,
+    pub swap: String,
+    pub swap_instructions: String,
+    pub price: String,
+}
+
+impl Default for JupiterEndpoints {
+    fn default() -> Self {
+        Self {
+            quote: "/quote".to_string(),
+            swap: "/swap".to_string(),
+            swap_instructions: "/swap-instructions".to_string(),
+            price: "/price".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JupiterClientConfig {
+    pub user_agent: Option<String>,
+    /// Sent as the `x-api-key` header, required by the paid Jupiter tier.
+    pub api_key: Option<String>,
+    /// Additional headers sent with every request.
+    pub headers: Vec<(String, String)>,
+    /// Endpoint paths, to be overridden for self-hosted or proxied deployments.
+    pub endpoints: JupiterEndpoints,
+    pub retry_policy: RetryPolicy,
+    /// Url of the token API, the mint is appended to it. Defaults to the public Jupiter one.
+    pub token_api_url: Option<String>,
+}
+
+/// Retries of the requests failing with a transport error or an HTTP 429 or 5xx status.
+/// The delay before the attempt `n + 1` is `base_delay * 2^(n - 1)` plus a random jitter.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, 1 disables retries.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    /// Upper bound of the random delay added to every backoff.
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(500),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let jitter = self.jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+        backoff.saturating_add(jitter)
+    }
+}
+
 pub struct JupiterClient {
     url: String,
     client: Client,
+    endpoints: JupiterEndpoints,
+    retry_policy: RetryPolicy,
+    token_api_url: String,
+    token_info_cache: Mutex<HashMap<Pubkey, TokenInfo>>,
 }
 
 impl JupiterClient {
@@ -151,9 +560,36 @@ impl JupiterClient {
         Self {
             url,
             client: Client::new(),
+            endpoints: JupiterEndpoints::default(),
+            retry_policy: RetryPolicy::default(),
+            token_api_url: DEFAULT_TOKEN_API_URL.to_string(),
+            token_info_cache: Mutex::new(HashMap::new()),
         }
     }
 
+    pub fn new_with_config(url: String, config: JupiterClientConfig) -> Result<Self> {
+        let mut headers = config.headers;
+        if let Some(api_key) = config.api_key {
+            headers.push(("x-api-key".to_string(), api_key));
+        }
+
+        let mut builder = Client::builder().default_headers(header_map(&headers)?);
+        if let Some(user_agent) = config.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        Ok(Self {
+            url,
+            client: builder.build()?,
+            endpoints: config.endpoints,
+            retry_policy: config.retry_policy,
+            token_api_url: config
+                .token_api_url
+                .unwrap_or(DEFAULT_TOKEN_API_URL.to_string()),
+            token_info_cache: Mutex::new(HashMap::new()),
+        })
+    }
+
     pub async fn get_quote(&self, params: GetQuoteParams) -> Result<QuoteResponse> {
         let mut query_params = vec![
             ("inputMint", params.input_mint.to_string()),
@@ -173,17 +609,21 @@ impl JupiterClient {
         if let Some(exclude_dexes) = params.exclude_dexes {
             query_params.push(("excludeDexes", exclude_dexes));
         }
+        if let Some(platform_fee_bps) = params.platform_fee_bps {
+            query_params.push(("platformFeeBps", platform_fee_bps.to_string()));
+        }
+        if let Some(max_accounts) = params.max_accounts {
+            query_params.push(("maxAccounts", max_accounts.to_string()));
+        }
 
-        let url = format!("{}/quote", self.url);
-        let response = self
+        let url = format!("{}{}", self.url, self.endpoints.quote);
+        let request = self
             .client
             .get(url)
             .header("Accept", "application/json")
             .query(&query_params)
-            .timeout(params.timeout)
-            .send()
-            .await?;
-        let response_string = response.text().await?;
+            .timeout(params.timeout);
+        let response_string = self.send_with_retry(request).await?;
         let quote_response: QuoteResponse =
             serde_json::from_str(&response_string).map_err(|err| {
                 anyhow!("get_amount_out error parsing response {response_string}: {err}")
@@ -191,16 +631,277 @@ impl JupiterClient {
         Ok(quote_response)
     }
 
+    /// Returns the price of each of the `ids` mints, in USD or in `vs_token` when given.
+    /// Mints the API does not price are omitted from the result.
+    pub async fn get_price(
+        &self,
+        ids: &[Pubkey],
+        vs_token: Option<Pubkey>,
+        timeout: Duration,
+    ) -> Result<HashMap<Pubkey, f64>> {
+        let ids_param = ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut query_params = vec![("ids", ids_param)];
+        if let Some(vs_token) = vs_token {
+            query_params.push(("vsToken", vs_token.to_string()));
+        }
+
+        let url = format!("{}{}", self.url, self.endpoints.price);
+        let request = self
+            .client
+            .get(url)
+            .header("Accept", "application/json")
+            .query(&query_params)
+            .timeout(timeout);
+        let response_string = self.send_with_retry(request).await?;
+        let price_response: PriceResponse = serde_json::from_str(&response_string)
+            .map_err(|err| anyhow!("get_price error parsing response {response_string}: {err}"))?;
+
+        let mut prices = HashMap::new();
+        for (id, price) in price_response.data {
+            let Some(price) = price else {
+                continue;
+            };
+            // The price is a string in v2 of the API and a number in v1.
+            let price = match &price.price {
+                serde_json::Value::String(price) => price.parse::<f64>().ok(),
+                value => value.as_f64(),
+            };
+            if let (Ok(id), Some(price)) = (parse_pubkey(&id), price) {
+                prices.insert(id, price);
+            }
+        }
+
+        Ok(prices)
+    }
+
+    /// Returns the metadata of a mint from the Jupiter token API, with the token program read
+    /// on-chain. Results are cached for the lifetime of the client.
+    pub async fn get_token_info(&self, rpc: &RpcClient, mint: &Pubkey) -> Result<TokenInfo> {
+        if let Some(token_info) = self.token_info_cache.lock().unwrap().get(mint) {
+            return Ok(token_info.clone());
+        }
+
+        let request = self
+            .client
+            .get(format!("{}/{}", self.token_api_url, mint))
+            .header(ACCEPT, "application/json");
+        let (response_string, mint_account) =
+            futures::future::join(self.send_with_retry(request), rpc.get_account(mint)).await;
+        let response_string = response_string?;
+        let token_response: TokenApiResponse =
+            serde_json::from_str(&response_string).map_err(|err| {
+                anyhow!("get_token_info error parsing response {response_string}: {err}")
+            })?;
+        let mint_account =
+            mint_account.map_err(|err| anyhow!("get_token_info get_account {}: {}", mint, err))?;
+
+        let token_info = TokenInfo {
+            mint: *mint,
+            symbol: token_response.symbol,
+            name: token_response.name,
+            decimals: token_response.decimals,
+            is_token_2022: mint_account.owner == spl_token_2022::id(),
+        };
+        self.token_info_cache
+            .lock()
+            .unwrap()
+            .insert(*mint, token_info.clone());
+        Ok(token_info)
+    }
+
+    /// Same as `get_quote`, also returning the server and round-trip timings of the request.
+    pub async fn get_quote_timed(
+        &self,
+        params: GetQuoteParams,
+    ) -> Result<(QuoteResponse, QuoteMeta)> {
+        let start = Instant::now();
+        let quote = self.get_quote(params).await?;
+        let client_rtt = start.elapsed();
+
+        let meta = QuoteMeta {
+            server_time_taken: quote.time_taken,
+            client_rtt,
+            context_slot: quote.context_slot,
+        };
+        Ok((quote, meta))
+    }
+
+    /// Requests a quote for each stage in order and returns the first one that succeeds.
+    /// Only "no route" errors move on to the next stage, other errors are returned right away.
+    pub async fn get_quote_staged(&self, stages: Vec<GetQuoteParams>) -> Result<QuoteResponse> {
+        let mut last_err = anyhow!("get_quote_staged no stages provided");
+        for (idx, params) in stages.into_iter().enumerate() {
+            match self.get_quote(params).await {
+                Ok(quote) => return Ok(quote),
+                Err(err)
+                    if !err
+                        .downcast_ref::<JupiterApiError>()
+                        .is_some_and(JupiterApiError::is_no_route) =>
+                {
+                    return Err(err)
+                }
+                Err(err) => {
+                    tracing::warn!("get_quote_staged stage {} failed: {}", idx, err);
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Tries a direct route first and falls back to multi-hop routes when no direct route
+    /// can be quoted.
+    pub async fn get_quote_direct_first(&self, params: GetQuoteParams) -> Result<QuoteResponse> {
+        self.get_quote_staged(vec![
+            GetQuoteParams {
+                only_direct_routes: true,
+                ..params.clone()
+            },
+            GetQuoteParams {
+                only_direct_routes: false,
+                ..params
+            },
+        ])
+        .await
+    }
+
+    /// Requests a quote for every variant of `base` concurrently and returns the best one with
+    /// the variant that produced it: the highest `out_amount` for ExactIn, the lowest
+    /// `in_amount` for ExactOut. Variants that fail are ignored.
+    pub async fn get_best_quote(
+        &self,
+        base: GetQuoteParams,
+        variants: Vec<QuoteVariant>,
+    ) -> Result<(QuoteResponse, QuoteVariant)> {
+        let is_exact_out = matches!(base.swap_mode, SwapMode::ExactOut);
+        let requests: Vec<_> = variants
+            .iter()
+            .map(|variant| self.get_quote(variant.apply(base.clone())))
+            .collect();
+        let responses = futures::future::join_all(requests).await;
+
+        let mut best: Option<(u64, QuoteResponse, QuoteVariant)> = None;
+        for (variant, response) in variants.into_iter().zip(responses) {
+            let quote = match response {
+                Ok(quote) => quote,
+                Err(err) => {
+                    tracing::warn!("get_best_quote variant {:?} failed: {}", variant, err);
+                    continue;
+                }
+            };
+            let amount = if is_exact_out {
+                quote.in_amount_u64()?
+            } else {
+                quote.out_amount_u64()?
+            };
+            let is_better = match &best {
+                None => true,
+                Some((best_amount, _, _)) if is_exact_out => amount < *best_amount,
+                Some((best_amount, _, _)) => amount > *best_amount,
+            };
+            if is_better {
+                best = Some((amount, quote, variant));
+            }
+        }
+
+        let (_, quote, variant) = best.ok_or(anyhow!("get_best_quote all variants failed"))?;
+        tracing::info!("get_best_quote winning variant {:?}", variant);
+        Ok((quote, variant))
+    }
+
+    /// Fetches a quote whose route does not go through any of the `excluded` AMM keys.
+    /// When a route uses an excluded pool, the labels of the offending DEXes are added to
+    /// `exclude_dexes` and the quote is requested again, up to a fixed number of attempts.
+    pub async fn get_quote_excluding_amm_keys(
+        &self,
+        params: GetQuoteParams,
+        excluded: &[String],
+    ) -> Result<QuoteResponse> {
+        let excluded: HashSet<&str> = excluded.iter().map(|key| key.as_str()).collect();
+        let mut params = params;
+        let mut excluded_labels: Vec<String> = params
+            .exclude_dexes
+            .as_ref()
+            .map(|dexes| dexes.split(',').map(|dex| dex.trim().to_string()).collect())
+            .unwrap_or_default();
+
+        for _ in 0..MAX_EXCLUDE_AMM_KEYS_ATTEMPTS {
+            let quote = self.get_quote(params.clone()).await?;
+            let bad_labels: Vec<String> = quote
+                .route_plan
+                .iter()
+                .filter(|step| excluded.contains(step.swap_info.amm_key.as_str()))
+                .map(|step| step.swap_info.label.clone())
+                .collect();
+            if bad_labels.is_empty() {
+                return Ok(quote);
+            }
+
+            let mut tightened = false;
+            for label in bad_labels {
+                if !excluded_labels.contains(&label) {
+                    excluded_labels.push(label);
+                    tightened = true;
+                }
+            }
+            if !tightened {
+                break;
+            }
+            params.exclude_dexes = Some(excluded_labels.join(","));
+        }
+
+        Err(anyhow!(
+            "get_quote_excluding_amm_keys no route found avoiding amm keys {:?}",
+            excluded
+        ))
+    }
+
+    /// Re-fetches a quote with the `params` that produced `original`, an ExactIn quote, and
+    /// errors if the new `out_amount` is worse than the original one by more than
+    /// `max_drift_bps`.
+    pub async fn verify_quote_still_valid(
+        &self,
+        params: GetQuoteParams,
+        original: &QuoteResponse,
+        max_drift_bps: u64,
+    ) -> Result<QuoteResponse> {
+        if original.swap_mode != SwapMode::ExactIn.to_string()
+            || params.swap_mode.to_string() != original.swap_mode
+        {
+            return Err(anyhow!(
+                "verify_quote_still_valid unsupported swap mode {}",
+                original.swap_mode
+            ));
+        }
+        if parse_pubkey(&original.input_mint)? != params.input_mint
+            || parse_pubkey(&original.output_mint)? != params.output_mint
+            || original.in_amount_u64()? != params.amount_in
+        {
+            return Err(anyhow!(
+                "verify_quote_still_valid params do not match the original quote"
+            ));
+        }
+
+        let amount_in = params.amount_in;
+        let fresh = self.get_quote(params).await?;
+
+        let original_out = original.out_amount_u64()?;
+        verify_requote(amount_in, original_out, fresh, max_drift_bps)
+    }
+
     pub async fn get_swap_transaction(&self, params: GetSwapParams) -> Result<SwapResponse> {
-        let response = self
+        params.validate_fee_account()?;
+        let request = self
             .client
-            .post(&format!("{}/swap", self.url))
+            .post(&format!("{}{}", self.url, self.endpoints.swap))
             .header(CONTENT_TYPE, "application/json")
             .header(ACCEPT, "application/json")
-            .json(&params)
-            .send()
-            .await?;
-        let response_string = response.text().await?;
+            .json(&params);
+        let response_string = self.send_with_retry(request).await?;
         let swap_response: SwapResponse =
             serde_json::from_str(&response_string).map_err(|err| {
                 anyhow!("get_swap_transaction error parsing response {response_string}: {err}")
@@ -212,19 +913,415 @@ impl JupiterClient {
         &self,
         params: GetSwapParams,
     ) -> Result<SwapInstructionsResponse> {
-        let response = self
+        params.validate_fee_account()?;
+        let request = self
             .client
-            .post(&format!("{}/swap-instructions", self.url))
+            .post(&format!("{}{}", self.url, self.endpoints.swap_instructions))
             .header(CONTENT_TYPE, "application/json")
             .header(ACCEPT, "application/json")
-            .json(&params)
-            .send()
-            .await?;
-        let response_string = response.text().await?;
+            .json(&params);
+        let response_string = self.send_with_retry(request).await?;
         let swap_response: SwapInstructionsResponse = serde_json::from_str(&response_string)
             .map_err(|err| {
                 anyhow!("get_swap_instructions error parsing response {response_string}: {err}")
             })?;
         Ok(swap_response)
     }
+
+    /// Sends a request, retrying transport errors and HTTP 429 or 5xx responses according to
+    /// the retry policy, and returns the body of the successful response. Non-success
+    /// responses are returned as a `JupiterApiError`.
+    async fn send_with_retry(&self, request: RequestBuilder) -> Result<String> {
+        let mut attempt = 1;
+        loop {
+            let attempt_request = request
+                .try_clone()
+                .ok_or(anyhow!("send_with_retry request cannot be cloned"))?;
+            let err: anyhow::Error = match attempt_request.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    let response_string = response.text().await?;
+                    if status.is_success() {
+                        return Ok(response_string);
+                    }
+                    let api_error = JupiterApiError::new(status, &response_string);
+                    if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                        return Err(api_error.into());
+                    }
+                    api_error.into()
+                }
+                Err(err) => err.into(),
+            };
+
+            if attempt >= self.retry_policy.max_attempts {
+                return Err(err);
+            }
+            tracing::warn!(
+                "jupiter request attempt {}/{}: {}",
+                attempt,
+                self.retry_policy.max_attempts,
+                err
+            );
+            tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Error response of the Jupiter API, e.g. when no route is found, retrievable with
+/// `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone)]
+pub struct JupiterApiError {
+    pub status: u16,
+    pub error: String,
+    /// e.g. `COULD_NOT_FIND_ANY_ROUTE`, when returned by the API.
+    pub error_code: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JupiterErrorResponse {
+    error: String,
+    error_code: Option<String>,
+}
+
+/// Error codes returned when no route can be found for a quote.
+const NO_ROUTE_ERROR_CODES: [&str; 2] = ["COULD_NOT_FIND_ANY_ROUTE", "NO_ROUTES_FOUND"];
+
+impl JupiterApiError {
+    /// Whether the quote failed because no route was found, as opposed to e.g. rate limits.
+    pub fn is_no_route(&self) -> bool {
+        self.error_code
+            .as_deref()
+            .is_some_and(|error_code| NO_ROUTE_ERROR_CODES.contains(&error_code))
+    }
+
+    fn new(status: StatusCode, response_string: &str) -> Self {
+        match serde_json::from_str::<JupiterErrorResponse>(response_string) {
+            Ok(response) => Self {
+                status: status.as_u16(),
+                error: response.error,
+                error_code: response.error_code,
+            },
+            Err(_) => Self {
+                status: status.as_u16(),
+                error: response_string.to_string(),
+                error_code: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for JupiterApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error_code {
+            Some(error_code) => write!(
+                f,
+                "jupiter error {} {}: {}",
+                self.status, error_code, self.error
+            ),
+            None => write!(f, "jupiter error {}: {}", self.status, self.error),
+        }
+    }
+}
+
+impl std::error::Error for JupiterApiError {}
+
+/// Typed errors returned by quote verification, retrievable with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub enum QuoteDriftError {
+    /// The re-fetched quote spends more than the target input amount.
+    InAmountGrew { expected: u64, actual: u64 },
+    /// The re-fetched quote returns less than the expected output amount minus the tolerance.
+    OutAmountShrank {
+        expected: u64,
+        actual: u64,
+        max_drift_bps: u64,
+    },
+}
+
+impl fmt::Display for QuoteDriftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuoteDriftError::InAmountGrew { expected, actual } => {
+                write!(f, "quote in_amount grew: {} -> {}", expected, actual)
+            }
+            QuoteDriftError::OutAmountShrank {
+                expected,
+                actual,
+                max_drift_bps,
+            } => write!(
+                f,
+                "quote drifted beyond {} bps: out_amount {} -> {}",
+                max_drift_bps, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QuoteDriftError {}
+
+/// Verifies that a re-fetched quote does not spend more than `amount_in` and that its
+/// `out_amount` is not worse than `expected_out_amount` by more than `max_drift_bps`,
+/// returning the verified quote.
+pub fn verify_requote(
+    amount_in: u64,
+    expected_out_amount: u64,
+    requote: QuoteResponse,
+    max_drift_bps: u64,
+) -> Result<QuoteResponse> {
+    let requote_in = requote.in_amount_u64()?;
+    if requote_in > amount_in {
+        return Err(QuoteDriftError::InAmountGrew {
+            expected: amount_in,
+            actual: requote_in,
+        }
+        .into());
+    }
+
+    let requote_out = requote.out_amount_u64()?;
+    let min_out =
+        expected_out_amount as u128 * 10_000u128.saturating_sub(max_drift_bps as u128) / 10_000u128;
+    if (requote_out as u128) < min_out {
+        return Err(QuoteDriftError::OutAmountShrank {
+            expected: expected_out_amount,
+            actual: requote_out,
+            max_drift_bps,
+        }
+        .into());
+    }
+
+    Ok(requote)
+}
+
+/// Errors if the price implied by `quote`, in output tokens per input token, deviates from
+/// `reference_price` (e.g. an oracle price) by more than `max_deviation_pct` percent.
+pub fn validate_quote_against_reference(
+    quote: &QuoteResponse,
+    reference_price: f64,
+    input_decimals: u8,
+    output_decimals: u8,
+    max_deviation_pct: f64,
+) -> Result<()> {
+    if reference_price <= 0.0 {
+        return Err(anyhow!("invalid reference price {}", reference_price));
+    }
+
+    let quote_price = quote.effective_price(input_decimals, output_decimals)?;
+    let deviation_pct = (quote_price - reference_price).abs() / reference_price * 100.0;
+    if deviation_pct > max_deviation_pct {
+        return Err(anyhow!(
+            "quote price {} deviates {:.2}% from reference price {}",
+            quote_price,
+            deviation_pct,
+            reference_price
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the difference between the prioritization fee actually paid by `landed_tx` and the
+/// one quoted by Jupiter in `swap_response`. A positive value means more was paid than quoted.
+/// The paid prioritization fee is `meta.fee` minus the base fee of every signature.
+pub fn prioritization_fee_delta(
+    swap_response: &SwapResponse,
+    landed_tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<i64> {
+    let meta = landed_tx.transaction.meta.as_ref().ok_or(anyhow!(
+        "prioritization_fee_delta transaction meta not found"
+    ))?;
+
+    let num_signatures = match &landed_tx.transaction.transaction {
+        EncodedTransaction::Json(tx) => tx.signatures.len(),
+        EncodedTransaction::Accounts(tx) => tx.signatures.len(),
+        encoded_tx => encoded_tx
+            .decode()
+            .ok_or(anyhow!(
+                "prioritization_fee_delta could not decode transaction"
+            ))?
+            .signatures
+            .len(),
+    };
+
+    let base_fee = num_signatures as u64 * LAMPORTS_PER_SIGNATURE;
+    let paid_prioritization_fee = meta.fee.saturating_sub(base_fee);
+    Ok(paid_prioritization_fee as i64 - swap_response.prioritization_fee_lamports as i64)
+}
+
+/// Estimates the total lamports needed to execute a SOL to token swap: the input amount when
+/// the input mint is wrapped SOL, the priority fee, the Jito tip, and the rent of the output
+/// token account when it does not exist yet.
+pub async fn estimate_total_sol_cost(
+    quote: &QuoteResponse,
+    tip_lamports: u64,
+    priority_fee_lamports: u64,
+    output_ata_exists: bool,
+    rpc: &RpcClient,
+) -> Result<u64> {
+    let input_lamports = if parse_pubkey(&quote.input_mint)? == spl_token::native_mint::id() {
+        quote.in_amount_u64()?
+    } else {
+        0
+    };
+
+    let ata_rent = if output_ata_exists {
+        0
+    } else {
+        let output_mint = parse_pubkey(&quote.output_mint)?;
+        let mint_account = rpc
+            .get_account(&output_mint)
+            .await
+            .map_err(|err| anyhow!("estimate_total_sol_cost get_account: {}", err))?;
+        token_account_rent(rpc, mint_account.owner == spl_token_2022::id()).await?
+    };
+
+    input_lamports
+        .checked_add(priority_fee_lamports)
+        .and_then(|total| total.checked_add(tip_lamports))
+        .and_then(|total| total.checked_add(ata_rent))
+        .ok_or(anyhow!("estimate_total_sol_cost overflow"))
+}
+
+fn parse_amount(amount: &str, field: &str) -> Result<u64> {
+    amount
+        .parse::<u64>()
+        .map_err(|err| anyhow!("could not parse {} {}: {}", field, amount, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{
+        message::Message, signature::Signature, system_instruction::transfer,
+        transaction::Transaction,
+    };
+    use solana_trading_core::conversions::{bytes_to_base64, tx_to_base64};
+
+    fn swap_response(swap_transaction: String) -> SwapResponse {
+        SwapResponse {
+            swap_transaction,
+            last_valid_block_height: 0,
+            prioritization_fee_lamports: 0,
+        }
+    }
+
+    #[test]
+    fn swap_response_decodes_legacy_transaction() {
+        let payer = Pubkey::new_unique();
+        let ix = transfer(&payer, &Pubkey::new_unique(), 1);
+        let tx = Transaction::new_unsigned(Message::new(&[ix], Some(&payer)));
+        let swap_transaction = bytes_to_base64(&bincode::serialize(&tx).unwrap());
+
+        let decoded = swap_response(swap_transaction).transaction().unwrap();
+        assert!(matches!(decoded.message, VersionedMessage::Legacy(_)));
+        assert_eq!(decoded.message.static_account_keys()[0], payer);
+    }
+
+    #[test]
+    fn swap_response_decodes_v0_transaction() {
+        let payer = Pubkey::new_unique();
+        let ix = transfer(&payer, &Pubkey::new_unique(), 1);
+        let message = v0::Message::try_compile(&payer, &[ix], &[], Hash::default()).unwrap();
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(message),
+        };
+        let swap_transaction = tx_to_base64(&tx).unwrap();
+
+        let decoded = swap_response(swap_transaction).transaction().unwrap();
+        assert!(matches!(decoded.message, VersionedMessage::V0(_)));
+        assert_eq!(decoded.message.static_account_keys()[0], payer);
+    }
+
+    fn quote(in_amount: u64, out_amount: u64) -> QuoteResponse {
+        serde_json::from_value(serde_json::json!({
+            "inputMint": "So11111111111111111111111111111111111111112",
+            "inAmount": in_amount.to_string(),
+            "outputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+            "outAmount": out_amount.to_string(),
+            "otherAmountThreshold": out_amount.to_string(),
+            "swapMode": "ExactIn",
+            "slippageBps": 50,
+            "platformFee": null,
+            "priceImpactPct": "0",
+            "routePlan": [],
+            "contextSlot": 0,
+            "timeTaken": 0.0
+        }))
+        .unwrap()
+    }
+
+    fn swap_params(quote_response: QuoteResponse, fee_account: Option<Pubkey>) -> GetSwapParams {
+        GetSwapParams {
+            user_public_key: Pubkey::new_unique().to_string(),
+            wrap_and_unwrap_sol: true,
+            use_shared_accounts: true,
+            compute_unit_price_micro_lamports: 0,
+            as_legacy_transaction: false,
+            use_token_ledger: false,
+            dynamic_compute_unit_limit: true,
+            skip_user_accounts_rpc_calls: false,
+            quote_response,
+            fee_account,
+        }
+    }
+
+    #[test]
+    fn fee_account_must_match_platform_fee() {
+        let mut quote_with_fee = quote(1_000, 2_000);
+        quote_with_fee.platform_fee = Some(PlatformFee {
+            amount: "20".to_string(),
+            fee_bps: 100,
+        });
+        let fee_account = Some(Pubkey::new_unique());
+
+        assert!(swap_params(quote(1_000, 2_000), None)
+            .validate_fee_account()
+            .is_ok());
+        assert!(swap_params(quote_with_fee.clone(), fee_account)
+            .validate_fee_account()
+            .is_ok());
+        assert!(swap_params(quote_with_fee.clone(), None)
+            .validate_fee_account()
+            .is_err());
+        assert!(swap_params(quote_with_fee, Some(Pubkey::default()))
+            .validate_fee_account()
+            .is_err());
+        assert!(swap_params(quote(1_000, 2_000), fee_account)
+            .validate_fee_account()
+            .is_err());
+    }
+
+    #[test]
+    fn effective_price_same_decimals() {
+        let price = quote(2_000_000, 5_000_000).effective_price(6, 6).unwrap();
+        assert!((price - 2.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn effective_price_different_decimals() {
+        // 1 SOL (9 decimals) for 150 USDC (6 decimals).
+        let price = quote(1_000_000_000, 150_000_000)
+            .effective_price(9, 6)
+            .unwrap();
+        assert!((price - 150.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn effective_price_errors_on_zero_in_amount() {
+        assert!(quote(0, 1).effective_price(6, 6).is_err());
+    }
+
+    #[test]
+    fn only_no_route_errors_are_no_route() {
+        let no_route = JupiterApiError::new(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Could not find any route","errorCode":"COULD_NOT_FIND_ANY_ROUTE"}"#,
+        );
+        assert!(no_route.is_no_route());
+
+        let rate_limited = JupiterApiError::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests");
+        assert!(!rate_limited.is_no_route());
+    }
 }
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use solana_sdk::{bs58, signature::Keypair};
+
+const KEYPAIR_LENGTH: usize = 64;
+
+pub enum KeypairSource {
+    /// JSON file containing the keypair as a byte array, as written by the Solana CLI.
+    File(PathBuf),
+    /// Base58 encoded keypair, as exported by most wallets.
+    Base58(String),
+    /// Environment variable holding either a JSON byte array or a base58 encoded keypair.
+    EnvVar(String),
+}
+
+pub fn load_keypair(source: KeypairSource) -> Result<Keypair> {
+    match source {
+        KeypairSource::File(path) => {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|err| anyhow!("could not read key file {}: {}", path.display(), err))?;
+            let bytes = json_to_bytes(&content)
+                .map_err(|err| anyhow!("malformed key file {}: {}", path.display(), err))?;
+            keypair_from_bytes(&bytes)
+        }
+        KeypairSource::Base58(encoded) => keypair_from_bytes(&base58_to_bytes(&encoded)?),
+        KeypairSource::EnvVar(name) => {
+            let value = std::env::var(&name)
+                .map_err(|err| anyhow!("could not read env var {}: {}", name, err))?;
+            let value = value.trim();
+            let bytes = if value.starts_with('[') {
+                json_to_bytes(value)
+                    .map_err(|err| anyhow!("malformed keypair in env var {}: {}", name, err))?
+            } else {
+                base58_to_bytes(value)?
+            };
+            keypair_from_bytes(&bytes)
+        }
+    }
+}
+
+fn json_to_bytes(content: &str) -> Result<Vec<u8>> {
+    Ok(serde_json::from_str::<Vec<u8>>(content)?)
+}
+
+fn base58_to_bytes(encoded: &str) -> Result<Vec<u8>> {
+    bs58::decode(encoded)
+        .into_vec()
+        .map_err(|err| anyhow!("malformed base58 keypair: {}", err))
+}
+
+fn keypair_from_bytes(bytes: &[u8]) -> Result<Keypair> {
+    if bytes.len() != KEYPAIR_LENGTH {
+        return Err(anyhow!(
+            "wrong keypair length {}, expected {} bytes",
+            bytes.len(),
+            KEYPAIR_LENGTH
+        ));
+    }
+    Keypair::from_bytes(bytes).map_err(|err| anyhow!("invalid keypair: {}", err))
+}
@@ -1,4 +1,14 @@
+pub mod block_height;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cancel;
+mod http;
+pub mod instructions;
 pub mod jito_client;
 pub mod jupiter_client;
+pub mod keypair;
+pub mod parse;
+pub mod sender;
 pub mod signatures;
+pub mod simulation;
 pub mod token;
@@ -0,0 +1,14 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+/// Parses a base58 pubkey, e.g. loaded from a config file or an environment variable.
+pub fn parse_pubkey(s: &str) -> Result<Pubkey> {
+    Pubkey::from_str(s).map_err(|err| anyhow!("invalid pubkey {}: {}", s, err))
+}
+
+/// Parses a base58 transaction signature.
+pub fn parse_signature(s: &str) -> Result<Signature> {
+    Signature::from_str(s).map_err(|err| anyhow!("invalid signature {}: {}", s, err))
+}
@@ -0,0 +1,44 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::jito_client::JitoClient;
+
+/// Transport used to submit a signed transaction.
+/// Returns an identifier of the submission, which depends on the implementation.
+#[async_trait]
+pub trait TransactionSender: Send + Sync {
+    async fn send(&self, tx: &VersionedTransaction) -> Result<String>;
+}
+
+/// Sends transactions through Jito, returning the bundle id.
+#[async_trait]
+impl TransactionSender for JitoClient {
+    async fn send(&self, tx: &VersionedTransaction) -> Result<String> {
+        self.send_transaction(tx).await
+    }
+}
+
+/// Sends transactions through a plain RPC node, returning the transaction signature.
+pub struct RpcSender {
+    rpc_client: RpcClient,
+}
+
+impl RpcSender {
+    pub fn new(rpc_client: RpcClient) -> Self {
+        Self { rpc_client }
+    }
+}
+
+#[async_trait]
+impl TransactionSender for RpcSender {
+    async fn send(&self, tx: &VersionedTransaction) -> Result<String> {
+        let signature = self
+            .rpc_client
+            .send_transaction(tx)
+            .await
+            .map_err(|err| anyhow!("send_transaction: {}", err))?;
+        Ok(signature.to_string())
+    }
+}
@@ -1,19 +1,127 @@
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
 use solana_client::{
     nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
     rpc_config::RpcTransactionConfig, rpc_response::RpcConfirmedTransactionStatusWithSignature,
 };
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+    transaction::TransactionError,
+};
 use solana_trading_core::time::datetime_from_timestamp_sec;
 use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use tokio_util::sync::CancellationToken;
+
+use crate::cancel::check_cancelled;
+use crate::parse::parse_signature;
+
+pub const DEFAULT_BLOCK_TIME_CACHE_CAPACITY: usize = 100_000;
+
+/// Block times already seen for a signature, evicting the oldest entries past `capacity`.
+/// Owned by the caller and passed to the helpers of this module through their configs, use one
+/// cache per cluster.
+pub struct BlockTimeCache {
+    capacity: usize,
+    entries: Mutex<BlockTimeCacheEntries>,
+}
+
+#[derive(Default)]
+struct BlockTimeCacheEntries {
+    block_times: HashMap<Signature, i64>,
+    insertion_order: VecDeque<Signature>,
+}
+
+impl BlockTimeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(BlockTimeCacheEntries::default()),
+        }
+    }
+
+    pub fn get(&self, signature: &Signature) -> Option<i64> {
+        self.entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.block_times.get(signature).copied())
+    }
+
+    pub fn insert(&self, signature: Signature, block_time: i64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.block_times.insert(signature, block_time).is_some() {
+            return;
+        }
+        entries.insertion_order.push_back(signature);
+        while entries.insertion_order.len() > self.capacity {
+            if let Some(evicted) = entries.insertion_order.pop_front() {
+                entries.block_times.remove(&evicted);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map_or(0, |entries| entries.block_times.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for BlockTimeCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOCK_TIME_CACHE_CAPACITY)
+    }
+}
+
+/// Signature returned by `getSignaturesForAddress`, with the signature and block time parsed.
+#[derive(Debug, Clone)]
+pub struct SignatureInfo {
+    pub signature: Signature,
+    pub block_time: Option<DateTime<Utc>>,
+    pub slot: u64,
+    pub err: Option<TransactionError>,
+}
+
+impl TryFrom<RpcConfirmedTransactionStatusWithSignature> for SignatureInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RpcConfirmedTransactionStatusWithSignature) -> Result<Self> {
+        let signature = parse_signature(&value.signature)?;
+        let block_time = value
+            .block_time
+            .map(datetime_from_timestamp_sec)
+            .transpose()?;
+        Ok(Self {
+            signature,
+            block_time,
+            slot: value.slot,
+            err: value.err,
+        })
+    }
+}
 
 pub struct GetSignaturesSinceTimeConfig {
     pub ignore_failed: bool,
     pub limit: usize,
     pub commitment: CommitmentConfig,
     pub log_progress: bool,
+    /// Checked between pages, returning a `Cancelled` error when cancelled.
+    pub cancellation_token: Option<CancellationToken>,
+    /// Maximum number of signatures fetched, stopping before the timestamp is reached if needed.
+    pub max_total: Option<usize>,
+    /// Cache filled with the block times of the fetched signatures, none by default.
+    pub block_time_cache: Option<Arc<BlockTimeCache>>,
 }
 
 impl Default for GetSignaturesSinceTimeConfig {
@@ -23,6 +131,9 @@ impl Default for GetSignaturesSinceTimeConfig {
             commitment: CommitmentConfig::finalized(),
             log_progress: false,
             limit: 1000,
+            cancellation_token: None,
+            max_total: None,
+            block_time_cache: None,
         }
     }
 }
@@ -32,6 +143,8 @@ pub struct GetTransactionsFromSignaturesConfig {
     pub encoding: UiTransactionEncoding,
     pub commitment: CommitmentConfig,
     pub log_progress: bool,
+    /// Cache filled with the block times of the fetched transactions, none by default.
+    pub block_time_cache: Option<Arc<BlockTimeCache>>,
 }
 
 impl Default for GetTransactionsFromSignaturesConfig {
@@ -41,6 +154,28 @@ impl Default for GetTransactionsFromSignaturesConfig {
             encoding: UiTransactionEncoding::JsonParsed,
             commitment: CommitmentConfig::finalized(),
             log_progress: false,
+            block_time_cache: None,
+        }
+    }
+}
+
+/// Signatures fetched by `get_signatures_since_time` and its variants.
+#[derive(Debug, Clone)]
+pub struct SignaturesPage {
+    /// Signatures in descending order, from the newest to the oldest.
+    pub signatures: Vec<RpcConfirmedTransactionStatusWithSignature>,
+    /// Oldest signature fetched, before filtering, to pass as `before` to resume further back.
+    pub oldest_signature: Option<Signature>,
+    /// False when `max_total` was reached before covering the whole time window.
+    pub complete: bool,
+}
+
+impl SignaturesPage {
+    fn empty() -> Self {
+        Self {
+            signatures: vec![],
+            oldest_signature: None,
+            complete: true,
         }
     }
 }
@@ -52,39 +187,196 @@ pub async fn get_signatures_since_time(
     target: Pubkey,
     since_timestamp_sec: i64,
     config: GetSignaturesSinceTimeConfig,
-) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>> {
-    let mut signatures = rpc
-        .get_signatures_for_address_with_config(
-            &target,
-            GetConfirmedSignaturesForAddress2Config {
-                limit: Some(config.limit),
-                commitment: Some(config.commitment),
-                ..Default::default()
-            },
-        )
-        .await?;
+) -> Result<SignaturesPage> {
+    get_signatures_since_time_with_cursor(rpc, target, since_timestamp_sec, None, None, config)
+        .await
+}
 
-    if signatures.is_empty() {
-        return Ok(vec![]);
-    }
+/// Same as `get_signatures_since_time`, only fetching signatures older than `before` and newer
+/// than `until` when set. Passing back the returned `oldest_signature` as `before` resumes a
+/// catch-up, and passing the newest signature already processed as `until` only fetches the
+/// new ones.
+pub async fn get_signatures_since_time_with_cursor(
+    rpc: &RpcClient,
+    target: Pubkey,
+    since_timestamp_sec: i64,
+    before: Option<Signature>,
+    until: Option<Signature>,
+    config: GetSignaturesSinceTimeConfig,
+) -> Result<SignaturesPage> {
+    let mut pager = SignaturePager::new(since_timestamp_sec, config.limit, config.max_total);
+    let mut before = before;
 
-    let mut oldest_signature = &signatures[signatures.len() - 1];
-    let mut oldest_blocktime = oldest_signature.block_time.unwrap();
+    loop {
+        let page = rpc
+            .get_signatures_for_address_with_config(
+                &target,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until,
+                    limit: Some(config.limit),
+                    commitment: Some(config.commitment),
+                },
+            )
+            .await?;
+        let Some((next_before, oldest_blocktime)) = pager.push_page(page)? else {
+            break;
+        };
+        before = Some(next_before);
+
+        check_cancelled(&config.cancellation_token)?;
 
-    while oldest_blocktime > since_timestamp_sec {
         if config.log_progress {
             tracing::info!(
                 "get_signatures before {:?} {}",
                 datetime_from_timestamp_sec(oldest_blocktime),
-                oldest_signature.signature
+                next_before
             );
         }
+    }
+
+    let page = pager.finish(config.ignore_failed)?;
+    if let Some(cache) = &config.block_time_cache {
+        for s in page.signatures.iter() {
+            if let (Ok(signature), Some(block_time)) = (parse_signature(&s.signature), s.block_time)
+            {
+                cache.insert(signature, block_time);
+            }
+        }
+    }
+
+    Ok(page)
+}
+
+/// Paging state of `get_signatures_since_time_with_cursor`, kept apart from the RPC calls.
+struct SignaturePager {
+    since_timestamp_sec: i64,
+    limit: usize,
+    max_total: Option<usize>,
+    signatures: Vec<RpcConfirmedTransactionStatusWithSignature>,
+    seen_signatures: HashSet<String>,
+    complete: bool,
+}
+
+impl SignaturePager {
+    fn new(since_timestamp_sec: i64, limit: usize, max_total: Option<usize>) -> Self {
+        Self {
+            since_timestamp_sec,
+            limit,
+            max_total,
+            signatures: vec![],
+            seen_signatures: HashSet::new(),
+            complete: true,
+        }
+    }
 
-        let prev_signatures = rpc
+    /// Adds a page of signatures, returning the `before` cursor of the next page to fetch and
+    /// the oldest block time known so far, or `None` when paging is done.
+    fn push_page(
+        &mut self,
+        page: Vec<RpcConfirmedTransactionStatusWithSignature>,
+    ) -> Result<Option<(Signature, i64)>> {
+        let page_len = page.len();
+        // Some RPCs repeat the boundary signature in consecutive pages.
+        let seen_signatures = &mut self.seen_signatures;
+        self.signatures.extend(
+            page.into_iter()
+                .filter(|s| seen_signatures.insert(s.signature.clone())),
+        );
+        let (reached_max_total, truncated) = match self.max_total {
+            Some(max_total) if self.signatures.len() >= max_total => {
+                let truncated = self.signatures.len() > max_total;
+                self.signatures.truncate(max_total);
+                (true, truncated)
+            }
+            _ => (false, false),
+        };
+
+        let Some(oldest_signature) = self.signatures.last() else {
+            return Ok(None);
+        };
+        // The RPC may omit the block time of some signatures, use the oldest one known.
+        let oldest_blocktime = self.signatures.iter().rev().find_map(|s| s.block_time);
+        // A partial page means there is nothing older, or that `until` was reached.
+        if oldest_blocktime.is_some_and(|block_time| block_time <= self.since_timestamp_sec)
+            || (page_len < self.limit && !truncated)
+        {
+            return Ok(None);
+        }
+        if reached_max_total {
+            self.complete = false;
+            return Ok(None);
+        }
+        let Some(oldest_blocktime) = oldest_blocktime else {
+            return Err(anyhow!(
+                "get_signatures_since_time block time not found up to {}",
+                oldest_signature.signature
+            ));
+        };
+
+        Ok(Some((
+            parse_signature(&oldest_signature.signature)?,
+            oldest_blocktime,
+        )))
+    }
+
+    /// Drops the signatures older than `since_timestamp_sec`, and the failed ones when
+    /// `ignore_failed` is set.
+    fn finish(self, ignore_failed: bool) -> Result<SignaturesPage> {
+        let mut signatures = self.signatures;
+        let oldest_signature = signatures
+            .last()
+            .map(|s| parse_signature(&s.signature))
+            .transpose()?;
+
+        // Signatures are sorted, so the ones without block time are within the window unless
+        // older than the first one known to be out of it.
+        if let Some(index) = signatures.iter().position(|s| {
+            s.block_time
+                .is_some_and(|block_time| block_time < self.since_timestamp_sec)
+        }) {
+            signatures.truncate(index);
+        }
+        if ignore_failed {
+            signatures.retain(|s| s.err.is_none());
+        }
+
+        Ok(SignaturesPage {
+            signatures,
+            oldest_signature,
+            complete: self.complete,
+        })
+    }
+}
+
+/// Returns the signatures for a given address with a block time within `[start_sec, end_sec]`,
+/// in descending order. An `end_sec` in the future is clamped to now. The signatures newer than
+/// the window are paged through without being kept, to find the anchor to page from.
+pub async fn get_signatures_between_times(
+    rpc: &RpcClient,
+    target: Pubkey,
+    start_sec: i64,
+    end_sec: i64,
+    config: GetSignaturesSinceTimeConfig,
+) -> Result<SignaturesPage> {
+    let end_sec = end_sec.min(Utc::now().timestamp());
+    if start_sec > end_sec {
+        return Err(anyhow!(
+            "get_signatures_between_times start {} is after end {}",
+            start_sec,
+            end_sec
+        ));
+    }
+
+    let mut before: Option<Signature> = None;
+    loop {
+        check_cancelled(&config.cancellation_token)?;
+
+        let page = rpc
             .get_signatures_for_address_with_config(
                 &target,
                 GetConfirmedSignaturesForAddress2Config {
-                    before: Some(Signature::from_str(&oldest_signature.signature).unwrap()),
+                    before,
                     limit: Some(config.limit),
                     commitment: Some(config.commitment),
                     ..Default::default()
@@ -92,17 +384,80 @@ pub async fn get_signatures_since_time(
             )
             .await?;
 
-        signatures.extend(prev_signatures);
-        oldest_signature = &signatures[signatures.len() - 1];
-        oldest_blocktime = oldest_signature.block_time.unwrap();
+        let first_in_window = page
+            .iter()
+            .position(|s| s.block_time.is_some_and(|block_time| block_time <= end_sec));
+        match first_in_window {
+            Some(0) => break,
+            Some(index) => {
+                before = Some(parse_signature(&page[index - 1].signature)?);
+                break;
+            }
+            None => {
+                let Some(oldest_signature) = page.last() else {
+                    return Ok(SignaturesPage::empty());
+                };
+                if page.len() < config.limit {
+                    return Ok(SignaturesPage::empty());
+                }
+                before = Some(parse_signature(&oldest_signature.signature)?);
+            }
+        }
     }
 
-    signatures.retain(|s| s.block_time.unwrap() >= since_timestamp_sec);
-    if config.ignore_failed {
-        signatures.retain(|s| s.err.is_none());
+    let mut page =
+        get_signatures_since_time_with_cursor(rpc, target, start_sec, before, None, config).await?;
+    page.signatures
+        .retain(|s| s.block_time.is_none_or(|block_time| block_time <= end_sec));
+
+    Ok(page)
+}
+
+/// Same as `get_signatures_since_time`, returning parsed `SignatureInfo`s and whether the
+/// whole time window was covered, as in `SignaturesPage::complete`.
+pub async fn get_signature_infos_since_time(
+    rpc: &RpcClient,
+    target: Pubkey,
+    since_timestamp_sec: i64,
+    config: GetSignaturesSinceTimeConfig,
+) -> Result<(Vec<SignatureInfo>, bool)> {
+    let page = get_signatures_since_time(rpc, target, since_timestamp_sec, config).await?;
+    let signature_infos = page
+        .signatures
+        .into_iter()
+        .map(SignatureInfo::try_from)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((signature_infos, page.complete))
+}
+
+/// Returns the block time of a signature, using the block times cached by previous calls
+/// before falling back to `getTransaction`.
+pub async fn block_time_for_signature(
+    rpc: &RpcClient,
+    cache: &BlockTimeCache,
+    signature: &Signature,
+) -> Result<i64> {
+    if let Some(block_time) = cache.get(signature) {
+        return Ok(block_time);
     }
 
-    Ok(signatures)
+    let tx = rpc
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let block_time = tx
+        .block_time
+        .ok_or(anyhow!("block time not found for {}", signature))?;
+    cache.insert(*signature, block_time);
+
+    Ok(block_time)
 }
 
 pub async fn get_transactions_from_signatures(
@@ -143,9 +498,19 @@ pub async fn get_transactions_from_signatures(
             })
             .collect::<Vec<_>>();
 
-        for res in futures::future::join_all(requests).await {
+        for (sig, res) in signatures_batch
+            .iter()
+            .zip(futures::future::join_all(requests).await)
+        {
             match res {
-                Ok(tx) => transactions.push(tx),
+                Ok(tx) => {
+                    if let (Some(cache), Some(block_time)) =
+                        (&config.block_time_cache, tx.block_time)
+                    {
+                        cache.insert(*sig, block_time);
+                    }
+                    transactions.push(tx)
+                }
                 Err(err) => tracing::error!("{}", err),
             }
         }
@@ -160,3 +525,174 @@ pub async fn get_transactions_from_signatures(
 
     Ok(transactions)
 }
+
+/// Same as `get_transactions_from_signatures`, yielding the transactions as each batch
+/// completes instead of collecting them, so that long backfills do not buffer every
+/// transaction in memory. Transactions that cannot be fetched are yielded as errors.
+pub fn get_transactions_from_signatures_stream(
+    rpc: &RpcClient,
+    signatures: Vec<Signature>,
+    config: GetTransactionsFromSignaturesConfig,
+) -> impl Stream<Item = Result<EncodedConfirmedTransactionWithStatusMeta>> + '_ {
+    let n = signatures.len();
+    let batches: Vec<Vec<Signature>> = signatures
+        .chunks(config.batch_size.max(1))
+        .map(|batch| batch.to_vec())
+        .collect();
+    let encoding = config.encoding;
+    let commitment = config.commitment;
+    let log_progress = config.log_progress;
+    let block_time_cache = config.block_time_cache;
+
+    let mut fetched = 0;
+    futures::stream::iter(batches)
+        .then(move |signatures_batch| {
+            fetched += signatures_batch.len();
+            if log_progress {
+                tracing::info!(
+                    "current_idx_max {}/{} ({:.2}%)",
+                    fetched,
+                    n,
+                    fetched as f64 / n as f64 * 100.0
+                );
+            }
+
+            let block_time_cache = block_time_cache.clone();
+            async move {
+                let requests = signatures_batch
+                    .iter()
+                    .map(|sig| {
+                        rpc.get_transaction_with_config(
+                            sig,
+                            RpcTransactionConfig {
+                                encoding: Some(encoding),
+                                commitment: Some(commitment),
+                                max_supported_transaction_version: Some(0),
+                            },
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                signatures_batch
+                    .iter()
+                    .zip(futures::future::join_all(requests).await)
+                    .map(|(sig, res)| {
+                        let tx = res.map_err(|err| anyhow!("get_transaction {}: {}", sig, err))?;
+                        if let (Some(cache), Some(block_time)) = (&block_time_cache, tx.block_time)
+                        {
+                            cache.insert(*sig, block_time);
+                        }
+                        Ok(tx)
+                    })
+                    .collect::<Vec<_>>()
+            }
+        })
+        .flat_map(futures::stream::iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature_status(block_time: Option<i64>) -> RpcConfirmedTransactionStatusWithSignature {
+        RpcConfirmedTransactionStatusWithSignature {
+            signature: Signature::new_unique().to_string(),
+            slot: 0,
+            err: None,
+            memo: None,
+            block_time,
+            confirmation_status: None,
+        }
+    }
+
+    #[test]
+    fn pager_keeps_signatures_without_block_time_inside_the_window() {
+        let page = vec![
+            signature_status(Some(130)),
+            signature_status(None),
+            signature_status(Some(110)),
+            signature_status(None),
+            signature_status(Some(90)),
+        ];
+        let expected: Vec<String> = page[..4].iter().map(|s| s.signature.clone()).collect();
+
+        let mut pager = SignaturePager::new(100, 10, None);
+        assert!(pager.push_page(page).unwrap().is_none());
+        let signatures: Vec<String> = pager
+            .finish(false)
+            .unwrap()
+            .signatures
+            .into_iter()
+            .map(|s| s.signature)
+            .collect();
+        assert_eq!(signatures, expected);
+    }
+
+    #[test]
+    fn pager_pages_from_the_oldest_known_block_time() {
+        let page = vec![
+            signature_status(Some(130)),
+            signature_status(Some(120)),
+            signature_status(None),
+        ];
+        let last_signature = parse_signature(&page[2].signature).unwrap();
+
+        let mut pager = SignaturePager::new(100, 3, None);
+        let (before, oldest_blocktime) = pager.push_page(page).unwrap().unwrap();
+        assert_eq!(before, last_signature);
+        assert_eq!(oldest_blocktime, 120);
+    }
+
+    #[test]
+    fn pager_drops_signatures_repeated_across_pages() {
+        let first_page = vec![
+            signature_status(Some(130)),
+            signature_status(Some(120)),
+            signature_status(Some(110)),
+        ];
+        let second_page = vec![
+            first_page[2].clone(),
+            signature_status(Some(100)),
+            signature_status(Some(90)),
+        ];
+        let expected: Vec<String> = first_page
+            .iter()
+            .chain(second_page[1..2].iter())
+            .map(|s| s.signature.clone())
+            .collect();
+
+        let mut pager = SignaturePager::new(100, 3, None);
+        assert!(pager.push_page(first_page).unwrap().is_some());
+        assert!(pager.push_page(second_page).unwrap().is_none());
+        let signatures: Vec<String> = pager
+            .finish(false)
+            .unwrap()
+            .signatures
+            .into_iter()
+            .map(|s| s.signature)
+            .collect();
+        assert_eq!(signatures, expected);
+    }
+
+    #[test]
+    fn pager_errors_when_no_block_time_is_known() {
+        let page = vec![signature_status(None), signature_status(None)];
+
+        let mut pager = SignaturePager::new(100, 2, None);
+        assert!(pager.push_page(page).is_err());
+    }
+
+    #[test]
+    fn block_time_cache_evicts_the_oldest_entries() {
+        let cache = BlockTimeCache::new(2);
+        let signatures: Vec<Signature> = (0..3).map(|_| Signature::new_unique()).collect();
+        for (block_time, signature) in signatures.iter().enumerate() {
+            cache.insert(*signature, block_time as i64);
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&signatures[0]), None);
+        assert_eq!(cache.get(&signatures[1]), Some(1));
+        assert_eq!(cache.get(&signatures[2]), Some(2));
+    }
+}
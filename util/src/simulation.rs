@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::InstructionError,
+    transaction::{TransactionError, VersionedTransaction},
+};
+
+const CUSTOM_ERROR_LOG_PREFIX: &str = "custom program error: 0x";
+
+/// Outcome of a transaction simulation.
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub success: bool,
+    pub error: Option<String>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+    /// Index of the top-level instruction that failed.
+    pub failing_instruction_index: Option<usize>,
+    /// Custom error code returned by the failing program.
+    pub program_error_code: Option<u32>,
+}
+
+/// Simulates a transaction and identifies which instruction failed and with which program
+/// error code, from the transaction error or, when it does not say, from the logs.
+pub async fn simulate_and_explain(
+    rpc: &RpcClient,
+    tx: &VersionedTransaction,
+) -> Result<SimulationReport> {
+    let result = rpc
+        .simulate_transaction(tx)
+        .await
+        .map_err(|err| anyhow!("simulate_transaction: {}", err))?
+        .value;
+    let logs = result.logs.unwrap_or_default();
+
+    let (mut failing_instruction_index, mut program_error_code) = match &result.err {
+        Some(TransactionError::InstructionError(index, instruction_error)) => {
+            let code = match instruction_error {
+                InstructionError::Custom(code) => Some(*code),
+                _ => None,
+            };
+            (Some(*index as usize), code)
+        }
+        _ => (None, None),
+    };
+    if result.err.is_some() {
+        let (log_index, log_code) = parse_failure_from_logs(&logs);
+        failing_instruction_index = failing_instruction_index.or(log_index);
+        program_error_code = program_error_code.or(log_code);
+    }
+
+    Ok(SimulationReport {
+        success: result.err.is_none(),
+        error: result.err.map(|err| err.to_string()),
+        logs,
+        units_consumed: result.units_consumed,
+        failing_instruction_index,
+        program_error_code,
+    })
+}
+
+/// Finds the top-level instruction whose invocation failed, counting the `invoke [1]` logs,
+/// and the custom error code reported in the failure log.
+fn parse_failure_from_logs(logs: &[String]) -> (Option<usize>, Option<u32>) {
+    let mut instruction_index: Option<usize> = None;
+    for log in logs {
+        if log.starts_with("Program ") && log.ends_with(" invoke [1]") {
+            instruction_index = Some(instruction_index.map_or(0, |index| index + 1));
+        } else if log.starts_with("Program ") && log.contains(" failed: ") {
+            let code = log.find(CUSTOM_ERROR_LOG_PREFIX).and_then(|pos| {
+                u32::from_str_radix(&log[pos + CUSTOM_ERROR_LOG_PREFIX.len()..], 16).ok()
+            });
+            return (instruction_index, code);
+        }
+    }
+    (None, None)
+}
@@ -1,9 +1,140 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
 
 use anyhow::{anyhow, Result};
-use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
-use spl_associated_token_account::get_associated_token_address_with_program_id;
+use solana_account_decoder::UiAccountData;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_request::TokenAccountsFilter,
+    rpc_response::RpcKeyedAccount,
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, instruction::Instruction,
+    native_token::lamports_to_sol, program_pack::Pack, pubkey::Pubkey,
+};
+use solana_trading_core::conversions::units_to_ui_number;
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::extension::{
+    transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+    BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
+
+use crate::parse::parse_pubkey;
+
+/// Maximum number of accounts accepted by `getMultipleAccounts`.
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+pub const DEFAULT_TOKEN_PROGRAM_CACHE_CAPACITY: usize = 10_000;
+
+/// Token program of each mint already detected by `detect_token_program`, evicting the oldest
+/// entries past `capacity`. A mint's program never changes, but mints differ between clusters:
+/// owned by the caller, use one cache per cluster.
+pub struct TokenProgramCache {
+    capacity: usize,
+    entries: Mutex<TokenProgramCacheEntries>,
+}
+
+#[derive(Default)]
+struct TokenProgramCacheEntries {
+    programs: HashMap<Pubkey, Pubkey>,
+    insertion_order: VecDeque<Pubkey>,
+}
+
+impl TokenProgramCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(TokenProgramCacheEntries::default()),
+        }
+    }
+
+    pub fn get(&self, mint: &Pubkey) -> Option<Pubkey> {
+        self.entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.programs.get(mint).copied())
+    }
+
+    pub fn insert(&self, mint: Pubkey, program_id: Pubkey) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.programs.insert(mint, program_id).is_some() {
+            return;
+        }
+        entries.insertion_order.push_back(mint);
+        while entries.insertion_order.len() > self.capacity {
+            if let Some(evicted) = entries.insertion_order.pop_front() {
+                entries.programs.remove(&evicted);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map_or(0, |entries| entries.programs.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for TokenProgramCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_PROGRAM_CACHE_CAPACITY)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBalance {
+    pub amount: u64,
+    pub ui_amount: f64,
+    pub decimals: u8,
+}
+
+/// Token account held by an owner, as returned by `getTokenAccountsByOwner`.
+#[derive(Debug, Clone)]
+pub struct TokenAccountInfo {
+    pub pubkey: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub decimals: u8,
+    pub is_token_2022: bool,
+}
+
+/// Options of `get_spl_balances_with_config`.
+#[derive(Debug, Clone)]
+pub struct SplBalancesConfig {
+    pub commitment: CommitmentConfig,
+    /// Accounts read per `getMultipleAccounts` request, at most 100.
+    pub batch_size: usize,
+}
+
+impl Default for SplBalancesConfig {
+    fn default() -> Self {
+        Self {
+            commitment: CommitmentConfig::confirmed(),
+            batch_size: MAX_MULTIPLE_ACCOUNTS,
+        }
+    }
+}
+
+/// Native and SPL balances of an owner, as returned by `get_portfolio`.
+#[derive(Debug, Clone)]
+pub struct Portfolio {
+    pub native_lamports: u64,
+    pub native_sol: f64,
+    pub tokens: HashMap<Pubkey, TokenBalance>,
+    /// Mints whose balance could not be fetched, e.g. when the associated token account does
+    /// not exist, with the error.
+    pub errors: HashMap<Pubkey, String>,
+}
 
 pub fn mint_to_associated_token_account(
     owner: &Pubkey,
@@ -28,32 +159,617 @@ pub fn mints_to_associated_token_accounts(
         .collect()
 }
 
+/// Returns the program owning `mint`, either SPL Token or Token-2022, using the programs
+/// cached by previous calls before falling back to `getAccount`.
+pub async fn detect_token_program(
+    rpc: &RpcClient,
+    cache: &TokenProgramCache,
+    mint: &Pubkey,
+) -> Result<Pubkey> {
+    if let Some(program_id) = cache.get(mint) {
+        return Ok(program_id);
+    }
+
+    let account = rpc
+        .get_account(mint)
+        .await
+        .map_err(|err| anyhow!("detect_token_program get_account {}: {}", mint, err))?;
+    if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+        return Err(anyhow!(
+            "detect_token_program {} is owned by {}, not a token program",
+            mint,
+            account.owner
+        ));
+    }
+
+    cache.insert(*mint, account.owner);
+    Ok(account.owner)
+}
+
+/// Returns the associated token account of `owner` for `mint`, detecting the mint's token
+/// program.
+pub async fn associated_token_account_auto(
+    rpc: &RpcClient,
+    cache: &TokenProgramCache,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<Pubkey> {
+    let program_id = detect_token_program(rpc, cache, mint).await?;
+    Ok(get_associated_token_address_with_program_id(
+        owner,
+        mint,
+        &program_id,
+    ))
+}
+
+/// Instruction creating the associated token account of `owner` for `mint`, succeeding
+/// without changes when the account already exists.
+pub fn create_associated_token_account_idempotent_ix(
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    create_associated_token_account_idempotent(payer, owner, mint, token_program)
+}
+
+/// Returns the instructions creating the associated token accounts of `owner` that do not
+/// exist yet, checked with `getMultipleAccounts`.
+pub async fn ensure_atas(
+    rpc: &RpcClient,
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+) -> Result<Vec<Instruction>> {
+    let atas = mints_to_associated_token_accounts(owner, mints);
+
+    let mut instructions = vec![];
+    for (ata_batch, mint_batch) in atas
+        .chunks(MAX_MULTIPLE_ACCOUNTS)
+        .zip(mints.chunks(MAX_MULTIPLE_ACCOUNTS))
+    {
+        let accounts = rpc
+            .get_multiple_accounts(ata_batch)
+            .await
+            .map_err(|err| anyhow!("ensure_atas get_multiple_accounts: {}", err))?;
+        for ((mint, is_token_2022), account) in mint_batch.iter().zip(accounts) {
+            if account.is_some() {
+                continue;
+            }
+            let token_program = if *is_token_2022 {
+                spl_token_2022::id()
+            } else {
+                spl_token::id()
+            };
+            instructions.push(create_associated_token_account_idempotent_ix(
+                payer,
+                owner,
+                mint,
+                &token_program,
+            ));
+        }
+    }
+
+    Ok(instructions)
+}
+
+/// Instruction closing the associated token account `ata` of `owner` for `mint`, sending its
+/// rent to `owner`. The account must be empty.
+pub fn close_ata_ix(
+    owner: &Pubkey,
+    ata: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Result<Instruction> {
+    let expected_ata = get_associated_token_address_with_program_id(owner, mint, token_program);
+    if *ata != expected_ata {
+        return Err(anyhow!(
+            "close_ata_ix {} is not the associated token account of {} for {}",
+            ata,
+            owner,
+            mint
+        ));
+    }
+
+    if *token_program == spl_token_2022::id() {
+        spl_token_2022::instruction::close_account(token_program, ata, owner, owner, &[])
+            .map_err(|err| anyhow!("close_ata_ix: {}", err))
+    } else if *token_program == spl_token::id() {
+        spl_token::instruction::close_account(token_program, ata, owner, owner, &[])
+            .map_err(|err| anyhow!("close_ata_ix: {}", err))
+    } else {
+        Err(anyhow!(
+            "close_ata_ix {} is not a token program",
+            token_program
+        ))
+    }
+}
+
+/// Returns the instructions closing the associated token accounts of `owner` that hold no
+/// tokens, to recover their rent. Token-2022 accounts still holding withheld transfer fees
+/// cannot be closed and are skipped, as are the addresses that do not hold a token account.
+pub async fn close_empty_atas(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+) -> Result<Vec<Instruction>> {
+    let atas = mints_to_associated_token_accounts(owner, mints);
+
+    let mut instructions = vec![];
+    for (ata_batch, mint_batch) in atas
+        .chunks(MAX_MULTIPLE_ACCOUNTS)
+        .zip(mints.chunks(MAX_MULTIPLE_ACCOUNTS))
+    {
+        let accounts = rpc
+            .get_multiple_accounts(ata_batch)
+            .await
+            .map_err(|err| anyhow!("close_empty_atas get_multiple_accounts: {}", err))?;
+        instructions.extend(close_empty_ata_ixs(owner, ata_batch, mint_batch, accounts));
+    }
+
+    Ok(instructions)
+}
+
+/// Instructions of `close_empty_atas` for a batch of associated token accounts, skipping with
+/// a warning the ones that cannot be unpacked or closed so that they do not block the others.
+fn close_empty_ata_ixs(
+    owner: &Pubkey,
+    atas: &[Pubkey],
+    mints: &[(Pubkey, bool)],
+    accounts: Vec<Option<Account>>,
+) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    for ((ata, (mint, _)), account) in atas.iter().zip(mints).zip(accounts) {
+        let Some(account) = account else {
+            continue;
+        };
+        let token_account =
+            match StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data) {
+                Ok(token_account) => token_account,
+                Err(err) => {
+                    tracing::warn!("close_empty_atas skipping {}: {}", ata, err);
+                    continue;
+                }
+            };
+        if token_account.base.amount != 0 {
+            continue;
+        }
+        let has_withheld_fees = token_account
+            .get_extension::<TransferFeeAmount>()
+            .is_ok_and(|fee_amount| u64::from(fee_amount.withheld_amount) != 0);
+        if has_withheld_fees {
+            continue;
+        }
+        match close_ata_ix(owner, ata, mint, &account.owner) {
+            Ok(instruction) => instructions.push(instruction),
+            Err(err) => tracing::warn!("close_empty_atas skipping {}: {}", ata, err),
+        }
+    }
+
+    instructions
+}
+
+/// Returns the native SOL balance of `owner` in lamports.
+pub async fn get_native_balance(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    commitment: CommitmentConfig,
+) -> Result<u64> {
+    Ok(rpc
+        .get_balance_with_commitment(owner, commitment)
+        .await
+        .map_err(|err| anyhow!("error getting native balance for {}: {}", owner, err))?
+        .value)
+}
+
 pub async fn get_spl_balances(
     rpc: &RpcClient,
     owner: &Pubkey,
     mints: &Vec<(Pubkey, bool)>,
 ) -> HashMap<Pubkey, Result<f64>> {
-    let atas = mints_to_associated_token_accounts(owner, mints);
+    get_spl_balances_with_commitment(rpc, owner, mints, CommitmentConfig::confirmed()).await
+}
 
-    let requests: Vec<_> = atas
+/// Fetches the same balances at each of the `commitments`, e.g. `processed` and `confirmed`,
+/// to detect balances that are still settling.
+pub async fn get_spl_balances_multi_commitment(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+    commitments: &[CommitmentConfig],
+) -> HashMap<Pubkey, HashMap<CommitmentConfig, Result<f64>>> {
+    let requests: Vec<_> = commitments
         .iter()
-        .map(|i| rpc.get_token_account_balance_with_commitment(&i, CommitmentConfig::confirmed()))
+        .map(|commitment| get_spl_balances_with_commitment(rpc, owner, mints, *commitment))
         .collect();
+    let responses = futures::future::join_all(requests).await;
+
+    let mut balances: HashMap<Pubkey, HashMap<CommitmentConfig, Result<f64>>> = HashMap::new();
+    for (commitment, commitment_balances) in commitments.iter().zip(responses) {
+        for (mint, balance_result) in commitment_balances {
+            balances
+                .entry(mint)
+                .or_default()
+                .insert(*commitment, balance_result);
+        }
+    }
+
+    balances
+}
 
-    let responses: Vec<_> = futures::future::join_all(requests).await;
-
-    let mut balances: HashMap<Pubkey, Result<f64>> = HashMap::new();
-    for (idx, response) in responses.iter().enumerate() {
-        let mint = mints[idx].0;
-        let balance_result = match response {
-            Ok(res) => res
-                .value
-                .ui_amount
-                .ok_or(anyhow!("ui amount not found for {}", mint)),
-            Err(err) => Err(anyhow!("error getting balance for {}: {}", mint, err)),
+async fn get_spl_balances_with_commitment(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+    commitment: CommitmentConfig,
+) -> HashMap<Pubkey, Result<f64>> {
+    get_spl_balances_with_config(
+        rpc,
+        owner,
+        mints,
+        SplBalancesConfig {
+            commitment,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Same as `get_spl_balances`, reading the token accounts and their mints with
+/// `getMultipleAccounts` in batches of `config.batch_size`, one batch at a time, so that many
+/// mints do not flood the RPC with concurrent requests.
+pub async fn get_spl_balances_with_config(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+    config: SplBalancesConfig,
+) -> HashMap<Pubkey, Result<f64>> {
+    get_token_balances_with_config(rpc, owner, mints, config)
+        .await
+        .into_iter()
+        .map(|(mint, balance)| (mint, balance.map(|balance| balance.ui_amount)))
+        .collect()
+}
+
+/// Same as `get_spl_balances_with_config`, also returning the raw amounts and the decimals.
+pub async fn get_token_balances_with_config(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+    config: SplBalancesConfig,
+) -> HashMap<Pubkey, Result<TokenBalance>> {
+    let atas = mints_to_associated_token_accounts(owner, mints);
+    let mint_pubkeys: Vec<Pubkey> = mints.iter().map(|(mint, _)| *mint).collect();
+    let batch_size = config.batch_size.clamp(1, MAX_MULTIPLE_ACCOUNTS);
+
+    let mut balances: HashMap<Pubkey, Result<TokenBalance>> = HashMap::new();
+    for (ata_batch, mint_batch) in atas.chunks(batch_size).zip(mint_pubkeys.chunks(batch_size)) {
+        let (ata_response, mint_response) = futures::future::join(
+            rpc.get_multiple_accounts_with_commitment(ata_batch, config.commitment),
+            rpc.get_multiple_accounts_with_commitment(mint_batch, config.commitment),
+        )
+        .await;
+        let (ata_accounts, mint_accounts) = match (ata_response, mint_response) {
+            (Ok(ata_response), Ok(mint_response)) => (ata_response.value, mint_response.value),
+            (Err(err), _) | (_, Err(err)) => {
+                for mint in mint_batch {
+                    balances.insert(
+                        *mint,
+                        Err(anyhow!("error getting balance for {}: {}", mint, err)),
+                    );
+                }
+                continue;
+            }
         };
-        balances.insert(mint, balance_result);
+
+        for ((mint, ata_account), mint_account) in
+            mint_batch.iter().zip(ata_accounts).zip(mint_accounts)
+        {
+            balances.insert(*mint, token_balance(mint, ata_account, mint_account));
+        }
     }
 
     balances
 }
+
+/// Unpacks the balance of a SPL Token or Token-2022 account from its data and its mint's.
+fn token_balance(
+    mint: &Pubkey,
+    token_account: Option<Account>,
+    mint_account: Option<Account>,
+) -> Result<TokenBalance> {
+    let token_account = token_account.ok_or(anyhow!("token account not found for {}", mint))?;
+    let mint_account = mint_account.ok_or(anyhow!("mint account not found for {}", mint))?;
+
+    let amount = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&token_account.data)
+        .map_err(|err| anyhow!("error unpacking token account for {}: {}", mint, err))?
+        .base
+        .amount;
+    let decimals = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data)
+        .map_err(|err| anyhow!("error unpacking mint {}: {}", mint, err))?
+        .base
+        .decimals;
+
+    Ok(TokenBalance {
+        amount,
+        ui_amount: units_to_ui_number(amount, decimals as u32),
+        decimals,
+    })
+}
+
+/// Returns the rent-exempt minimum in lamports for an associated token account.
+/// Token-2022 associated token accounts carry the `ImmutableOwner` extension, so they are
+/// larger than SPL Token accounts.
+pub async fn token_account_rent(rpc: &RpcClient, is_token_2022: bool) -> Result<u64> {
+    let account_len = if is_token_2022 {
+        ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&[
+            ExtensionType::ImmutableOwner,
+        ])
+        .map_err(|err| anyhow!("token_account_rent account len: {}", err))?
+    } else {
+        spl_token::state::Account::LEN
+    };
+
+    rpc.get_minimum_balance_for_rent_exemption(account_len)
+        .await
+        .map_err(|err| anyhow!("get_minimum_balance_for_rent_exemption: {}", err))
+}
+
+/// Returns the decimals of SPL Token and Token-2022 mints, read with `getMultipleAccounts`.
+/// Mints that do not exist or cannot be unpacked have an error entry.
+pub async fn get_mint_decimals(
+    rpc: &RpcClient,
+    mints: &[Pubkey],
+) -> Result<HashMap<Pubkey, Result<u8>>> {
+    let mut decimals: HashMap<Pubkey, Result<u8>> = HashMap::new();
+    for mint_batch in mints.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = rpc
+            .get_multiple_accounts(mint_batch)
+            .await
+            .map_err(|err| anyhow!("get_mint_decimals get_multiple_accounts: {}", err))?;
+        for (mint, account) in mint_batch.iter().zip(accounts) {
+            decimals.insert(*mint, mint_decimals(mint, account));
+        }
+    }
+
+    Ok(decimals)
+}
+
+fn mint_decimals(mint: &Pubkey, account: Option<Account>) -> Result<u8> {
+    let account = account.ok_or(anyhow!("mint account not found for {}", mint))?;
+    if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+        return Err(anyhow!(
+            "mint {} is owned by {}, not a token program",
+            mint,
+            account.owner
+        ));
+    }
+
+    Ok(
+        StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&account.data)
+            .map_err(|err| anyhow!("error unpacking mint {}: {}", mint, err))?
+            .base
+            .decimals,
+    )
+}
+
+/// Returns the transfer fee configuration of a Token-2022 mint, or `None` when the mint does not
+/// charge transfer fees. `TransferFeeConfig::calculate_epoch_fee` gives the fee withheld from a
+/// transfer, i.e. the difference between the amount sent and the amount received.
+pub async fn get_transfer_fee_config(
+    rpc: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Option<TransferFeeConfig>> {
+    let account = rpc
+        .get_account(mint)
+        .await
+        .map_err(|err| anyhow!("get_account: {}", err))?;
+    if account.owner != spl_token_2022::id() {
+        return Ok(None);
+    }
+
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&account.data)
+        .map_err(|err| anyhow!("get_transfer_fee_config unpack: {}", err))?;
+    Ok(mint_state
+        .get_extension::<TransferFeeConfig>()
+        .ok()
+        .copied())
+}
+
+/// Lists all the SPL Token and Token-2022 accounts held by `owner`.
+/// `getTokenAccountsByOwner` is not paginated, so each program is queried once.
+pub async fn get_all_token_accounts(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+) -> Result<Vec<TokenAccountInfo>> {
+    let (token_accounts, token_2022_accounts) = futures::future::join(
+        rpc.get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(spl_token::id())),
+        rpc.get_token_accounts_by_owner(
+            owner,
+            TokenAccountsFilter::ProgramId(spl_token_2022::id()),
+        ),
+    )
+    .await;
+    let token_accounts =
+        token_accounts.map_err(|err| anyhow!("get_token_accounts_by_owner: {}", err))?;
+    let token_2022_accounts =
+        token_2022_accounts.map_err(|err| anyhow!("get_token_accounts_by_owner 2022: {}", err))?;
+
+    let mut accounts = Vec::with_capacity(token_accounts.len() + token_2022_accounts.len());
+    for keyed_account in token_accounts {
+        accounts.push(parse_token_account_info(keyed_account, false)?);
+    }
+    for keyed_account in token_2022_accounts {
+        accounts.push(parse_token_account_info(keyed_account, true)?);
+    }
+
+    Ok(accounts)
+}
+
+fn parse_token_account_info(
+    keyed_account: RpcKeyedAccount,
+    is_token_2022: bool,
+) -> Result<TokenAccountInfo> {
+    let pubkey = parse_pubkey(&keyed_account.pubkey)?;
+    let UiAccountData::Json(parsed_account) = keyed_account.account.data else {
+        return Err(anyhow!("token account {} is not json parsed", pubkey));
+    };
+
+    let info = &parsed_account.parsed["info"];
+    let mint = info["mint"]
+        .as_str()
+        .and_then(|mint| parse_pubkey(mint).ok())
+        .ok_or(anyhow!("mint not found for token account {}", pubkey))?;
+    let amount = info["tokenAmount"]["amount"]
+        .as_str()
+        .and_then(|amount| amount.parse::<u64>().ok())
+        .ok_or(anyhow!("amount not found for token account {}", pubkey))?;
+    let decimals = info["tokenAmount"]["decimals"]
+        .as_u64()
+        .ok_or(anyhow!("decimals not found for token account {}", pubkey))?;
+
+    Ok(TokenAccountInfo {
+        pubkey,
+        mint,
+        amount,
+        decimals: decimals as u8,
+        is_token_2022,
+    })
+}
+
+/// Returns the native SOL balance and the balances of the given mints held by `owner`, the
+/// token balances being read in batches as in `get_spl_balances_with_config`. Mints whose
+/// balance cannot be fetched are reported in `errors` instead of `tokens`.
+pub async fn get_portfolio(
+    rpc: &RpcClient,
+    owner: &Pubkey,
+    mints: &Vec<(Pubkey, bool)>,
+) -> Result<Portfolio> {
+    let config = SplBalancesConfig::default();
+    let (native_lamports, token_balances) = futures::future::join(
+        get_native_balance(rpc, owner, config.commitment),
+        get_token_balances_with_config(rpc, owner, mints, config),
+    )
+    .await;
+    let native_lamports = native_lamports?;
+
+    let mut tokens: HashMap<Pubkey, TokenBalance> = HashMap::new();
+    let mut errors: HashMap<Pubkey, String> = HashMap::new();
+    for (mint, balance) in token_balances {
+        match balance {
+            Ok(balance) => {
+                tokens.insert(mint, balance);
+            }
+            Err(err) => {
+                errors.insert(mint, err.to_string());
+            }
+        }
+    }
+
+    Ok(Portfolio {
+        native_lamports,
+        native_sol: lamports_to_sol(native_lamports),
+        tokens,
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed_account<T: Pack>(state: T, owner: Pubkey) -> Option<Account> {
+        let mut data = vec![0; T::LEN];
+        T::pack(state, &mut data).unwrap();
+        Some(Account {
+            lamports: 1,
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        })
+    }
+
+    #[test]
+    fn token_balance_reads_amount_and_decimals() {
+        let mint = Pubkey::new_unique();
+        let token_account = packed_account(
+            spl_token::state::Account {
+                mint,
+                owner: Pubkey::new_unique(),
+                amount: 1_500_000,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            },
+            spl_token::id(),
+        );
+        let mint_account = packed_account(
+            spl_token::state::Mint {
+                decimals: 6,
+                is_initialized: true,
+                ..Default::default()
+            },
+            spl_token::id(),
+        );
+
+        let balance = token_balance(&mint, token_account, mint_account).unwrap();
+        assert_eq!(balance.amount, 1_500_000);
+        assert_eq!(balance.decimals, 6);
+        assert_eq!(balance.ui_amount, 1.5);
+        assert!(token_balance(&mint, None, None).is_err());
+    }
+
+    fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Option<Account> {
+        packed_account(
+            spl_token::state::Account {
+                mint,
+                owner,
+                amount,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            },
+            spl_token::id(),
+        )
+    }
+
+    #[test]
+    fn close_empty_atas_skips_invalid_accounts() {
+        let owner = Pubkey::new_unique();
+        let mints: Vec<(Pubkey, bool)> = (0..4).map(|_| (Pubkey::new_unique(), false)).collect();
+        let atas = mints_to_associated_token_accounts(&owner, &mints);
+        let accounts = vec![
+            // Lamports sent to the associated token account address.
+            Some(Account {
+                lamports: 1,
+                data: vec![],
+                owner: solana_sdk::system_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            }),
+            token_account(mints[1].0, owner, 0),
+            // Token account owned by an unexpected program.
+            token_account(mints[2].0, owner, 0).map(|account| Account {
+                owner: Pubkey::new_unique(),
+                ..account
+            }),
+            token_account(mints[3].0, owner, 1),
+        ];
+
+        let instructions = close_empty_ata_ixs(&owner, &atas, &mints, accounts);
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].accounts[0].pubkey, atas[1]);
+    }
+
+    #[test]
+    fn token_program_cache_evicts_the_oldest_entries() {
+        let cache = TokenProgramCache::new(2);
+        let mints: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for mint in mints.iter() {
+            cache.insert(*mint, spl_token::id());
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&mints[0]), None);
+        assert_eq!(cache.get(&mints[2]), Some(spl_token::id()));
+    }
+}